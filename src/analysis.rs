@@ -0,0 +1,139 @@
+use attack;
+use dice;
+
+/// Difference in average results made by a group of effects of a disposition.
+#[derive(Debug)]
+pub struct Marginal {
+  /// Indices of effects in the disposition.
+  pub effects: Vec<usize>,
+  pub delta: attack::Scores,
+}
+
+/// For each effect, how much worse the disposition gets without it, all other effects kept.
+pub fn effect_ablation(disposition: &attack::Disposition) -> Vec<Marginal> {
+  let full = disposition.average_scores();
+  (0..disposition.effects.len())
+    .map(|idx| {
+      let mut without = disposition.clone();
+      without.effects.remove(idx);
+      Marginal { effects: vec![idx], delta: difference(&full, &without.average_scores()) }
+    })
+    .collect()
+}
+
+/// For each subset of effects, how much better the disposition gets with only these effects
+/// compared to no effects at all. Effects keep their relative order.
+/// Number of evaluations grows exponentially, so use it for small sets of effects.
+pub fn effect_subsets(disposition: &attack::Disposition) -> Vec<Marginal> {
+  let count = disposition.effects.len();
+  let mut bare = disposition.clone();
+  bare.effects.clear();
+  let bare = bare.average_scores();
+  (1..1usize << count)
+    .map(|mask| {
+      let effects = (0..count).filter(|idx| mask & (1 << idx) != 0).collect::<Vec<_>>();
+      let mut subset = disposition.clone();
+      subset.effects = effects.iter().map(|&idx| disposition.effects[idx]).collect();
+      Marginal { delta: difference(&subset.average_scores(), &bare), effects }
+    })
+    .collect()
+}
+
+/// Difference in average results made by a single die.
+#[derive(Debug)]
+pub struct DieMarginal {
+  pub die: dice::Type,
+  pub delta: attack::Scores,
+}
+
+/// For each type of dice the attacker can add to the pool,
+/// how much better the disposition gets with one more die of that type.
+pub fn die_additions(disposition: &attack::Disposition) -> Vec<DieMarginal> {
+  let current = disposition.average_scores();
+  dice::pool_types
+    .iter()
+    .map(|&die| {
+      let mut with = disposition.clone();
+      with.dice.push(die);
+      DieMarginal { die, delta: difference(&with.average_scores(), &current) }
+    })
+    .collect()
+}
+
+/// For each die of the disposition, how much worse the disposition gets without it.
+/// Marginals go in the same order as dice.
+pub fn die_removals(disposition: &attack::Disposition) -> Vec<DieMarginal> {
+  let current = disposition.average_scores();
+  disposition.dice
+    .iter()
+    .enumerate()
+    .map(|(idx, &die)| {
+      let mut without = disposition.clone();
+      without.dice.remove(idx);
+      DieMarginal { die, delta: difference(&current, &without.average_scores()) }
+    })
+    .collect()
+}
+
+fn difference(lhs: &attack::Scores, rhs: &attack::Scores) -> attack::Scores {
+  let mut delta = *lhs;
+  attack::update(&mut delta, rhs, |diff, part| {
+    *diff -= *part;
+  });
+  delta
+}
+
+#[cfg(test)]
+mod test {
+  use analysis::*;
+  use dice;
+  use effects;
+
+  #[test]
+  fn ablation_of_luck() {
+    let hit_luck = &effects::LuckForHit{};
+    let crit_luck = &effects::LuckForCrit{};
+    let marginals = effect_ablation(&attack::Disposition::test_shot(vec![dice::White], vec![hit_luck, crit_luck]));
+
+    assert_eq!(marginals.len(), 2);
+    assert_eq!(marginals[0].effects, vec![0]);
+    assert_eq!((marginals[0].delta[attack::Damage] * 20.0).round() as i64, 2);
+    assert_eq!((marginals[0].delta[attack::Hits] * 20.0).round() as i64, 2);
+    assert_eq!((marginals[1].delta[attack::Damage] * 20.0).round() as i64, 0);
+    assert_eq!((marginals[1].delta[attack::Crits] * 40.0).round() as i64, 13);
+  }
+
+  #[test]
+  fn adding_black_die() {
+    let marginals = die_additions(&attack::Disposition::test_shot(vec![dice::White], vec![]));
+    let black = marginals.iter().find(|marginal| marginal.die == dice::Black).unwrap();
+
+    assert!(marginals.iter().map(|marginal| marginal.die).eq(dice::pool_types.iter().cloned()));
+    assert_eq!((black.delta[attack::Damage] * 20.0 * 12.0).round() as i64, 79);
+    assert_eq!((black.delta[attack::Hits] * 20.0 * 12.0).round() as i64, 2);
+  }
+
+  #[test]
+  fn removing_red_die() {
+    let mut armored = attack::Disposition::test_shot(vec![dice::White], vec![]);
+    armored.dice.push(dice::Red);
+    armored.characteristics.target.physical_armor.soft_armor = 2;
+    armored.characteristics.base_score[dice::Damage] = 3;
+    let marginals = die_removals(&armored);
+
+    assert_eq!(marginals.len(), 2);
+    assert_eq!(marginals[1].die, dice::Red);
+    assert_eq!((marginals[1].delta[attack::Damage] * 20.0 * 12.0).round() as i64, -11 * 10);
+  }
+
+  #[test]
+  fn subsets_cover_all_combinations() {
+    let hit_luck = &effects::LuckForHit{};
+    let crit_luck = &effects::LuckForCrit{};
+    let marginals = effect_subsets(&attack::Disposition::test_shot(vec![dice::White], vec![hit_luck, crit_luck]));
+
+    assert_eq!(marginals.iter().map(|m| m.effects.clone()).collect::<Vec<_>>(), vec![vec![0], vec![1], vec![0, 1]]);
+    assert_eq!((marginals[1].delta[attack::Crits] * 40.0).round() as i64, 11);
+    assert_eq!((marginals[2].delta[attack::Crits] * 40.0).round() as i64, 13);
+  }
+}
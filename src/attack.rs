@@ -1,7 +1,12 @@
+use std::mem;
+use std::ops::Range;
 use std::marker::PhantomData;
 use std::borrow::{ Cow, Borrow };
+use std::collections::HashMap;
+use std::hash::{ BuildHasherDefault, Hash, Hasher };
 use common::*;
 use cartesian_fold::IterExt;
+use distribution::{ Distribution, whole };
 use outcome;
 use dice;
 
@@ -12,21 +17,38 @@ outcome! {
   Crits,
   Actions,
   BrokenLegs,
+  Kills,
+  PhysicalDamage,
+  EnergyDamage,
+  RadiationDamage,
+  FireDamage,
+  Radiation,
+  WastedDamage,
 }
 
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct Disposition<'a> {
   pub dice: Vec<dice::Type>,
   pub characteristics: Characteristics,
   pub effects: Vec<&'a dyn Effect>,
+  /// Armour of models near the primary target against damage of the weapon, hit by its explosions.
+  /// Red dice are part of the attack roll, which is made once and resolved against every model
+  /// caught in the blast, so they apply to blast targets as well.
+  pub blast_targets: Vec<Armor>,
+  /// Verify that probabilities of branches yielded by effects sum up to 1 for every roll.
+  /// Panics otherwise. Useful while writing new effects.
+  pub check_conservation: bool,
 }
 
 impl<'a> Disposition<'a> {
   pub fn average_scores(&self) -> self::Scores {
+    let mut applicator = EffectApplicator::new(self.effects.clone());
+    applicator.memo = Some(Memo::default());
+    applicator.check_conservation = self.check_conservation;
     self.dice
       .iter()
       .map(|&die| { dice::RollFace::faces_of(die).iter().cloned() })
-      .cartesian_collections(|faces| { self.attack_scores(faces) })
+      .cartesian_collections(|faces| { applicator.apply_effects(&self.characteristics, faces) })
       .fold(Scores::default(), |mut acc, next| {
         update(&mut acc, &next.scores, |sum, part| {
           *sum += *part * next.probability;
@@ -35,58 +57,365 @@ impl<'a> Disposition<'a> {
       })
   }
 
-  fn attack_scores(&self, roll: &[dice::RollFace]) -> Outcome {
-    EffectApplicator::new(self.effects.clone())
-      .apply_effects(&self.characteristics, roll)
+  /// Passes every alteration made by effects to the tracer, for every possible roll.
+  pub fn trace(&self, tracer: &mut dyn Tracer) {
+    let mut applicator = EffectApplicator::new(self.effects.clone());
+    applicator.tracer = Some(tracer);
+    self.dice
+      .iter()
+      .map(|&die| { dice::RollFace::faces_of(die).iter().cloned() })
+      .cartesian_collections(|faces| { applicator.apply_effects(&self.characteristics, faces); })
+      .for_each(drop);
+  }
+
+  /// Calls `f` with results of every state an attack can end up in, along with its probability.
+  /// Unlike `average_scores`, keeps outcomes apart, so that their distribution can be built.
+  pub fn outcomes(&self, mut f: impl FnMut(&Scores, Real)) {
+    self.final_states(|alteration, probability| f(&alteration.scores, probability));
+  }
+
+  /// Calls `f` with every state an attack can end up in, along with its probability.
+  fn final_states(&self, mut f: impl FnMut(&Alteration, Real)) {
+    let mut applicator = EffectApplicator::new(self.effects.clone());
+    applicator.check_conservation = self.check_conservation;
+    self.dice
+      .iter()
+      .map(|&die| { dice::RollFace::faces_of(die).iter().cloned() })
+      .cartesian_collections(|faces| {
+        applicator.collected = Some(vec![]);
+        let outcome = applicator.apply_effects(&self.characteristics, faces);
+        for alteration in applicator.collected.take().unwrap() {
+          f(&alteration, outcome.probability * alteration.probability);
+        }
+      })
+      .for_each(drop);
+  }
+
+  /// Distribution of a whole-valued result, such as damage or hits.
+  pub fn distribution(&self, facet: Facet) -> Distribution {
+    let mut distribution = Distribution::default();
+    self.outcomes(|scores, probability| distribution.add(whole(scores[facet]), probability));
+    distribution
+  }
+
+  /// The same attack made against another target.
+  pub fn against(&self, target: &Target) -> Disposition<'a> {
+    let mut disposition = self.clone();
+    disposition.characteristics.target = target.clone();
+    disposition
+  }
+
+  /// Expected damage dealt by explosions to each of blast targets, in the same order as targets.
+  pub fn blast_damage(&self) -> Vec<Real> {
+    let mut damage = vec![0.0; self.blast_targets.len()];
+    self.final_states(|alteration, probability| {
+      let characteristics = &alteration.characteristics;
+      let (dice_outcome, _) = compute_output(characteristics, &alteration.roll);
+      let blast_damage = characteristics.explosion_damage * dice_outcome.scores[dice::Explosion];
+      if alteration.scores[Hits] == 0.0 || blast_damage <= 0 {
+        return;
+      }
+      for (sum, target) in damage.iter_mut().zip(&self.blast_targets) {
+        let armor = target_armor(characteristics, target, &alteration.roll, &dice_outcome.scores);
+        *sum += (blast_damage - armor.min(blast_damage)) as Real * probability;
+      }
+    });
+    damage
+  }
+
+  /// Tells how often each effect alters the case and how much it changes the results on average.
+  /// Reports go in the same order as effects.
+  pub fn explain(&self) -> Vec<EffectReport> {
+    let mut explanation = Explanation::new(self.effects.len());
+    self.trace(&mut explanation);
+    explanation.reports
   }
 }
 
-#[derive(Clone, Default)]
+#[cfg(test)]
+impl<'a> Disposition<'a> {
+  /// Disposition tests start from: it hits for 1 damage when skill rolled is up to 6.
+  pub fn test_shot(dice: Vec<dice::Type>, effects: Vec<&'a dyn Effect>) -> Disposition<'a> {
+    Disposition {
+      dice,
+      characteristics: Characteristics {
+        required_skill: 6,
+        base_score: dice::new_scores(&[(dice::Damage, 1)]),
+        ..Default::default()
+      },
+      effects,
+      ..Default::default()
+    }
+  }
+}
+
+#[cfg(test)]
+impl Target {
+  /// Target tests start from: it has no armour and goes down after given damage.
+  pub fn test_hp(hp: Score) -> Target {
+    Target { hp: Some(PositiveScore::new(hp).expect("hit points should be positive")), ..Default::default() }
+  }
+}
+
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
 pub struct Characteristics {
   pub base_score: dice::Scores,
   pub required_skill: Score,
+  pub damage_type: DamageType,
+  pub armor_rule: ArmorRule,
+  /// Armour pierced by the attack. Soft armour still has to be activated to be pierced.
+  pub ignored_soft_armor: Score,
+  pub ignored_hard_armor: Score,
+  /// What the weapon does when crit is scored on a hit.
+  pub crit_effect: CritEffect,
+  /// Damage of every explosion scored on a hit. It goes to the primary target as well as
+  /// to blast targets of the disposition.
+  pub explosion_damage: Score,
+  pub target: Target,
+}
+
+/// Model the attack is made against, as it is before the attack.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Target {
+  /// Armour against each type of damage.
+  pub physical_armor: Armor,
+  pub energy_armor: Armor,
+  pub radiation_armor: Armor,
+  pub fire_armor: Armor,
+  /// Hit points left. Damage beyond them is reported as `WastedDamage` instead,
+  /// and the target is killed once they are gone.
+  pub hp: Option<PositiveScore>,
+  pub wounded: bool,
+  /// Radiation points of every hit are reduced by rad resistance.
+  pub rad_resistance: Score,
+  /// Radiation points the target already has.
+  pub rad: Score,
+  /// Every time radiation points reach a multiple of the threshold, the target loses one more HP.
+  /// Without threshold radiation doesn't harm at all.
+  pub rad_threshold: Option<PositiveScore>,
+}
+
+/// Armour of a model against one type of damage.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Armor {
   pub soft_armor: Score,
   pub hard_armor: Score,
 }
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum DamageType {
+  #[default]
+  Physical,
+  Energy,
+  Radiation,
+  Fire,
+}
+
+impl DamageType {
+  /// Result which damage of this type is reported in, besides total `Damage`.
+  pub fn facet(self) -> Facet {
+    match self {
+      DamageType::Physical => PhysicalDamage,
+      DamageType::Energy => EnergyDamage,
+      DamageType::Radiation => RadiationDamage,
+      DamageType::Fire => FireDamage,
+    }
+  }
+}
+
+impl Target {
+  /// Armour against damage of the given type.
+  pub fn armor(&self, damage_type: DamageType) -> Armor {
+    match damage_type {
+      DamageType::Physical => self.physical_armor,
+      DamageType::Energy => self.energy_armor,
+      DamageType::Radiation => self.radiation_armor,
+      DamageType::Fire => self.fire_armor,
+    }
+  }
+}
+
+impl Characteristics {
+  /// Armour of the target against damage of the weapon.
+  pub fn target_armor(&self) -> Armor {
+    self.target.armor(self.damage_type)
+  }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum CritEffect {
+  /// Crits are only counted.
+  #[default]
+  Nothing,
+  /// Target gets broken legs.
+  Maim,
+  /// Every crit adds damage, before armour.
+  ExtraDamage(Score),
+  /// Armour doesn't stop any damage.
+  IgnoreArmor,
+  /// Wounded target is killed outright.
+  KillWounded,
+}
+
+/// How armour rolled on several dice, e.g. when target is in cover, is put together.
+/// In any case armour above soft armour left after shred doesn't count at all.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ArmorRule {
+  /// Armour of all dice is summed up.
+  #[default]
+  Sum,
+  /// Defender picks the single die which stops most damage.
+  Best,
+}
+
 pub fn compute_output(characteristics: &Characteristics, roll: &[dice::RollFace]) -> (dice::Outcome, Scores) {
-  let mut roll_outcome = roll.iter().fold(dice::Outcome::new(), |mut acc, face| {
+  resolve(characteristics, roll, &roll_outcome(roll))
+}
+
+/// Sums up scores of faces alone, without characteristics.
+pub fn roll_outcome(roll: &[dice::RollFace]) -> dice::Outcome {
+  roll.iter().fold(dice::Outcome::new(), |mut acc, face| {
     face.add_score(&mut acc);
     acc
-  });
+  })
+}
+
+/// Adds base score to scores of faces and works out results of the attack.
+/// Roll outcome should be the one of the roll, it is taken to avoid summing up faces again.
+pub fn resolve(
+  characteristics: &Characteristics,
+  roll_faces: &[dice::RollFace],
+  roll_outcome: &dice::Outcome,
+) -> (dice::Outcome, Scores) {
+  let mut roll_outcome = roll_outcome.clone();
   let roll = &mut roll_outcome.scores;
 
   dice::update(roll, &characteristics.base_score, |sum, part| {
     *sum += *part;
   });
 
-  let miss = roll[dice::Miss] > 0 || roll[dice::Skill] > characteristics.required_skill;
-  let hit = roll[dice::Hit] > 0 || !miss;
-  let scored_armor = roll[dice::Armor];
-  let shredded_armor = (characteristics.soft_armor - roll[dice::Shred]).max(0);
-  let aplied_soft_armor = if scored_armor > shredded_armor { 0 } else { scored_armor };
-  let damage = roll[dice::Damage];
-  let applied_armor = (aplied_soft_armor + characteristics.hard_armor).min(damage);
-  let damage = damage - applied_armor;
+  let hit = hits(characteristics, roll);
+  let crit = if hit { characteristics.crit_effect } else { CritEffect::Nothing };
+  let crits = roll[dice::Crit];
+  let damage = raw_damage(characteristics, roll);
+  let damage = if damage > 0 { damage - armor(characteristics, roll_faces, roll).min(damage) } else { 0 };
+  let target = &characteristics.target;
+  let rad = if hit { (roll[dice::Rad] - target.rad_resistance).max(0) } else { 0 };
+  let rad_damage = match target.rad_threshold {
+    Some(threshold) => {
+      let thresholds = |rad: Score| rad / threshold.get();
+      thresholds(target.rad + rad) - thresholds(target.rad)
+    },
+    None => 0,
+  };
+  // Damage of the hit goes first, radiation takes whatever hit points are left after it.
+  let (damage, rad_damage, wasted) = match target.hp {
+    Some(hp) => {
+      let dealt = damage.min(hp.get());
+      let rad_dealt = rad_damage.min(hp.get() - dealt);
+      (dealt, rad_dealt, damage - dealt + rad_damage - rad_dealt)
+    },
+    None => (damage, rad_damage, 0),
+  };
   let mut scores = Scores::default();
-  scores[Damage] = if hit { damage } else { 0 } as Real;
+  scores[Damage] = (damage + rad_damage) as Real;
+  scores[characteristics.damage_type.facet()] = damage as Real;
+  scores[RadiationDamage] += rad_damage as Real;
+  scores[WastedDamage] = wasted as Real;
   scores[Hits] = if hit { 1.0 } else { 0.0 };
-  scores[Crits] = roll[dice::Crit] as Real;
+  scores[Crits] = crits as Real;
   scores[Actions] = roll[dice::Action] as Real;
+  scores[Radiation] = rad as Real;
+  if target.hp.is_some_and(|hp| damage + rad_damage == hp.get()) {
+    scores[Kills] = 1.0;
+  }
+  if crits > 0 {
+    match crit {
+      CritEffect::Maim => scores[BrokenLegs] = 1.0,
+      CritEffect::KillWounded if target.wounded => scores[Kills] = 1.0,
+      _ => (),
+    }
+  }
   (roll_outcome, scores)
 }
 
+/// Whether the attack hits, given scores of the roll along with base score.
+fn hits(characteristics: &Characteristics, scores: &dice::Scores) -> bool {
+  scores[dice::Hit] > 0 || dice::passes(scores, characteristics.required_skill)
+}
+
+/// Damage of the hit before armour, given scores of the roll along with base score.
+/// Extra damage of crits and damage of explosions are included.
+pub fn raw_damage(characteristics: &Characteristics, scores: &dice::Scores) -> Score {
+  if !hits(characteristics, scores) {
+    return 0;
+  }
+  let crit_damage = match characteristics.crit_effect {
+    CritEffect::ExtraDamage(bonus) => bonus * scores[dice::Crit],
+    _ => 0,
+  };
+  scores[dice::Damage] + crit_damage + characteristics.explosion_damage * scores[dice::Explosion]
+}
+
+/// Armour which stops damage, given scores of the roll along with base score.
+/// There is none when a crit lets the hit ignore armour.
+pub fn armor(characteristics: &Characteristics, roll: &[dice::RollFace], scores: &dice::Scores) -> Score {
+  let crit_ignores_armor = characteristics.crit_effect == CritEffect::IgnoreArmor && scores[dice::Crit] > 0;
+  if crit_ignores_armor && hits(characteristics, scores) {
+    return 0;
+  }
+  target_armor(characteristics, &characteristics.target_armor(), roll, scores)
+}
+
+/// Armour of the given target which stops damage. Target could be either primary or caught by explosion.
+fn target_armor(characteristics: &Characteristics, target: &Armor, roll: &[dice::RollFace], scores: &dice::Scores) -> Score {
+  let soft_armor = activated_soft_armor(characteristics.armor_rule, target.soft_armor, roll, scores);
+  let soft_armor = (soft_armor - characteristics.ignored_soft_armor).max(0);
+  let hard_armor = (target.hard_armor - characteristics.ignored_hard_armor).max(0);
+  soft_armor + hard_armor
+}
+
+fn activated_soft_armor(armor_rule: ArmorRule, soft_armor: Score, roll: &[dice::RollFace], scores: &dice::Scores) -> Score {
+  let shredded_armor = (soft_armor - scores[dice::Shred]).max(0);
+  let applies = |armor: Score| if armor > shredded_armor { 0 } else { armor };
+  match armor_rule {
+    ArmorRule::Sum => applies(scores[dice::Armor]),
+    ArmorRule::Best => {
+      let dice_armor = roll.iter().map(|face| face.scores()[dice::Armor]).sum::<Score>();
+      let base_armor = scores[dice::Armor] - dice_armor;
+      roll.iter()
+        .map(|face| face.scores()[dice::Armor])
+        .filter(|&armor| armor > 0)
+        .map(|armor| applies(armor + base_armor))
+        .chain(Some(applies(base_armor)))
+        .max()
+        .unwrap()
+    },
+  }
+}
+
 pub struct Case<'a> {
   characteristics: Cow<'a, Characteristics>,
   dice_roll: Cow<'a, [dice::RollFace]>,
+  /// Scores of faces alone, kept up to date face by face.
+  roll_outcome: dice::Outcome,
   dice_outcome: dice::Outcome,
   scores: Scores,
+  /// Whether the case was updated since it went into the current effect.
+  altered: bool,
+  /// Buffer the roll is copied into once it is changed, so that changes don't allocate.
+  spare_roll: Vec<dice::RollFace>,
 }
 
 impl<'a> Case<'a> {
   pub fn update(&mut self, f: impl Fn(&mut CaseUpdater::<'_, 'a>)) {
-    f(&mut CaseUpdater { case: self });
-    let (dice_outcome, scores) = compute_output(&self.characteristics, &self.dice_roll);
+    let mut updater = CaseUpdater { case: self, roll_changed: false };
+    f(&mut updater);
+    if updater.roll_changed {
+      self.roll_outcome = roll_outcome(&self.dice_roll);
+    }
+    self.altered = true;
+    let (dice_outcome, scores) = resolve(&self.characteristics, &self.dice_roll, &self.roll_outcome);
     self.dice_outcome = dice_outcome;
     self.scores = scores;
   }
@@ -95,10 +424,24 @@ impl<'a> Case<'a> {
     self.scores[facet]
   }
 
+  pub fn results(&self) -> &Scores {
+    &self.scores
+  }
+
   pub fn score(&self, facet: dice::Facet) -> Score {
     self.dice_outcome.scores[facet]
   }
 
+  /// Armour which stops damage as the case is now, crits taken into account.
+  pub fn armor(&self) -> Score {
+    armor(&self.characteristics, &self.dice_roll, &self.dice_outcome.scores)
+  }
+
+  /// Damage of the hit before armour as the case is now, crits and explosions taken into account.
+  pub fn raw_damage(&self) -> Score {
+    raw_damage(&self.characteristics, &self.dice_outcome.scores)
+  }
+
   pub fn characteristics(&self) -> &Characteristics {
     &self.characteristics
   }
@@ -111,14 +454,33 @@ impl<'a> Case<'a> {
     Case {
       characteristics: Cow::Borrowed(self.characteristics.borrow()),
       dice_roll: Cow::Borrowed(&self.dice_roll),
+      roll_outcome: self.roll_outcome.clone(),
       dice_outcome: self.dice_outcome.clone(),
       scores: self.scores,
+      altered: self.altered,
+      spare_roll: vec![],
+    }
+  }
+
+  /// Roll to be changed. It is copied into the spare buffer first, unless it is already owned.
+  fn roll_to_change(&mut self) -> &mut Vec<dice::RollFace> {
+    if let Cow::Borrowed(roll) = self.dice_roll {
+      let mut owned = mem::take(&mut self.spare_roll);
+      owned.clear();
+      owned.extend_from_slice(roll);
+      self.dice_roll = Cow::Owned(owned);
+    }
+    match self.dice_roll {
+      Cow::Owned(ref mut roll) => roll,
+      Cow::Borrowed(_) => unreachable!(),
     }
   }
 }
 
 pub struct CaseUpdater<'u, 'o> {
   case: &'u mut Case<'o>,
+  /// Whether faces were changed in a way which requires to sum them up again.
+  roll_changed: bool,
 }
 
 impl<'u, 'o> CaseUpdater<'u, 'o> {
@@ -126,77 +488,671 @@ impl<'u, 'o> CaseUpdater<'u, 'o> {
     &mut self.case.characteristics.to_mut().base_score[facet]
   }
 
+  pub fn characteristics_mut(&mut self) -> &mut Characteristics {
+    self.case.characteristics.to_mut()
+  }
+
+  /// Gives away faces to change them at will. The whole roll is summed up again afterwards,
+  /// so prefer `set_face` where it does. Built-in effects don't need it, custom effects may.
+  #[allow(dead_code)]
   pub fn roll_mut(&mut self) -> &mut [dice::RollFace] {
-    self.case.dice_roll.to_mut()
+    self.roll_changed = true;
+    self.case.roll_to_change()
+  }
+
+  /// Replaces a single face. Only the difference between faces is applied to scores,
+  /// instead of summing up the whole roll again.
+  pub fn set_face(&mut self, idx: usize, face: dice::RollFace) {
+    let old = mem::replace(&mut self.case.roll_to_change()[idx], face);
+    if old.probability == 0.0 {
+      self.roll_changed = true;
+    } else {
+      old.remove_score(&mut self.case.roll_outcome);
+      face.add_score(&mut self.case.roll_outcome);
+    }
   }
+
+  /// Adds one more die to the roll, e.g. when an effect lets to roll an extra die.
+  pub fn add_face(&mut self, face: dice::RollFace) {
+    face.add_score(&mut self.case.roll_outcome);
+    self.case.roll_to_change().push(face);
+  }
+
+  pub fn restore(&mut self, alteration: &Alteration) {
+    self.roll_changed = true;
+    self.case.characteristics = Cow::Owned(alteration.characteristics.clone());
+    let roll = self.case.roll_to_change();
+    roll.clear();
+    roll.extend_from_slice(&alteration.roll);
+  }
+}
+
+/// State of a case after it went through a chain of effects,
+/// along with probability of reaching that state.
+pub struct Alteration {
+  pub characteristics: Characteristics,
+  pub roll: Vec<dice::RollFace>,
+  pub scores: Scores,
+  pub probability: Real,
 }
 
+/// What an effect did to a case in one of its alterations.
+pub struct Trace<'t> {
+  pub effect_idx: usize,
+  /// Probability to get to this alteration, including probability of the roll itself.
+  pub probability: Real,
+  pub scores_before: &'t dice::Scores,
+  pub scores_after: &'t dice::Scores,
+  pub result_before: &'t Scores,
+  pub result_after: &'t Scores,
+}
+
+impl<'t> Trace<'t> {
+  pub fn altered(&self) -> bool {
+    self.scores_before != self.scores_after || self.result_before != self.result_after
+  }
+}
+
+pub trait Tracer {
+  fn trace(&mut self, trace: &Trace);
+}
+
+impl<F: FnMut(&Trace)> Tracer for F {
+  fn trace(&mut self, trace: &Trace) {
+    self(trace)
+  }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct EffectReport {
+  /// Probability that effect changes anything in the case.
+  pub trigger_probability: Real,
+  /// Average change of results made by the effect itself, not counting effects after it.
+  pub contribution: Scores,
+}
+
+/// Tracer which aggregates traces into per-effect reports.
+pub struct Explanation {
+  pub reports: Vec<EffectReport>,
+}
+
+impl Explanation {
+  pub fn new(effects_count: usize) -> Explanation {
+    Explanation { reports: vec![Default::default(); effects_count] }
+  }
+}
+
+impl Tracer for Explanation {
+  fn trace(&mut self, trace: &Trace) {
+    if !trace.altered() {
+      return;
+    }
+    let report = &mut self.reports[trace.effect_idx];
+    report.trigger_probability += trace.probability;
+    for (facet, sum) in report.contribution.iter_mut() {
+      *sum += (trace.result_after[facet] - trace.result_before[facet]) * trace.probability;
+    }
+  }
+}
+
+/// Passes cases through effects. The same applicator is used for every roll of a disposition,
+/// so its buffers and memo are kept between rolls.
 pub struct EffectApplicator<'a> {
   effects: Vec<&'a dyn Effect>,
   effect_idx: usize,
-  outcome: Outcome,
-  probs: Vec<Real>,
+  /// Probability to get to the case which goes through the current effect,
+  /// from the innermost case which is being remembered.
+  probability: Real,
+  /// Probability of branches in which the current effect left the case as it was.
+  unaltered: Real,
+  /// Altered cases waiting to go through the rest of effects.
+  pending: Vec<Pending>,
+  frames: Vec<Frame>,
+  spares: Spares,
+  memo: Option<Memo>,
+  /// Alterations which went through all effects, if they are gathered instead of being accumulated.
+  collected: Option<Vec<Alteration>>,
+  tracer: Option<&'a mut dyn Tracer>,
+  /// Probability to get to the case which goes through the current effect, including the roll.
+  case_probability: Real,
+  roll_probability: Real,
+  entered: (dice::Scores, Scores),
+  check_conservation: bool,
 }
 
 pub struct LastAlteration { opaque: PhantomData<Opaque> }
 struct Opaque;
 
+/// Case waiting to go through an effect.
+struct State<'s> {
+  characteristics: Cow<'s, Characteristics>,
+  roll: Cow<'s, [dice::RollFace]>,
+  roll_outcome: dice::Outcome,
+  dice_outcome: dice::Outcome,
+  scores: Scores,
+}
+
+impl<'s> State<'s> {
+  fn of(case: &'s Case) -> State<'s> {
+    State {
+      characteristics: Cow::Borrowed(case.characteristics()),
+      roll: Cow::Borrowed(case.roll()),
+      roll_outcome: case.roll_outcome.clone(),
+      dice_outcome: case.dice_outcome.clone(),
+      scores: case.scores,
+    }
+  }
+
+  fn owned(case: &Case, spares: &mut Spares) -> State<'static> {
+    State {
+      characteristics: Cow::Owned(case.characteristics().clone()),
+      roll: Cow::Owned(spares.roll(case.roll())),
+      roll_outcome: case.roll_outcome.clone(),
+      dice_outcome: case.dice_outcome.clone(),
+      scores: case.scores,
+    }
+  }
+
+  fn case(&self, spares: &mut Spares) -> Case<'_> {
+    Case {
+      characteristics: Cow::Borrowed(&self.characteristics),
+      dice_roll: Cow::Borrowed(&self.roll),
+      roll_outcome: self.roll_outcome.clone(),
+      dice_outcome: self.dice_outcome.clone(),
+      scores: self.scores,
+      altered: false,
+      spare_roll: spares.rolls.pop().unwrap_or_default(),
+    }
+  }
+
+  /// State of the case once it is done with, without copying its roll again.
+  fn taken(case: Case, spares: &mut Spares) -> State<'static> {
+    if case.spare_roll.capacity() > 0 {
+      spares.rolls.push(case.spare_roll);
+    }
+    State {
+      characteristics: Cow::Owned(case.characteristics.into_owned()),
+      roll: Cow::Owned(match case.dice_roll {
+        Cow::Borrowed(roll) => spares.roll(roll),
+        Cow::Owned(roll) => roll,
+      }),
+      roll_outcome: case.roll_outcome,
+      dice_outcome: case.dice_outcome,
+      scores: case.scores,
+    }
+  }
+}
+
+/// Buffers which are not used at the moment, but will be needed again soon.
+#[derive(Default)]
+struct Spares {
+  rolls: Vec<Vec<dice::RollFace>>,
+}
+
+impl Spares {
+  fn roll(&mut self, faces: &[dice::RollFace]) -> Vec<dice::RollFace> {
+    let mut roll = self.rolls.pop().unwrap_or_default();
+    roll.clear();
+    roll.extend_from_slice(faces);
+    roll
+  }
+
+  fn recycle(&mut self, state: State) {
+    if let Cow::Owned(roll) = state.roll {
+      self.rolls.push(roll);
+    }
+  }
+
+  /// Takes back buffers of the case an effect is done with.
+  fn reclaim(&mut self, case: Case) {
+    if case.spare_roll.capacity() > 0 {
+      self.rolls.push(case.spare_roll);
+    }
+    if let Cow::Owned(roll) = case.dice_roll {
+      self.rolls.push(roll);
+    }
+  }
+}
+
+/// Average results of a case after it goes through the rest of effects,
+/// along with total probability of alterations which got there.
+#[derive(Clone, Copy, Default)]
+struct Value {
+  scores: Scores,
+  mass: Real,
+}
+
+impl Value {
+  fn add(&mut self, probability: Real, other: &Value) {
+    self.mass += probability * other.mass;
+    update(&mut self.scores, &other.scores, |sum, part| {
+      *sum += *part * probability;
+    });
+  }
+}
+
+/// Altered case waiting to go through the rest of effects.
+struct Pending {
+  /// Index of the effect the case goes through next.
+  effect_idx: usize,
+  state: State<'static>,
+  /// Probability to get to the case from the innermost case which is being remembered.
+  probability: Real,
+}
+
+/// Case which waits for values of all alterations made from it, either the roll itself
+/// or a case to be remembered in memo.
+struct Frame {
+  /// Index of the effect the case goes through.
+  effect_idx: usize,
+  /// Index of the case in memo, if it is remembered.
+  memo_idx: Option<usize>,
+  /// Number of pending cases when the frame was started. These belong to outer frames.
+  pending: usize,
+  /// Probability to get to the case from the outer frame.
+  probability: Real,
+  /// Probability to get to the case from the roll.
+  roll_probability: Real,
+  value: Value,
+}
+
+/// Values of cases which already went through effects, by the effect they entered.
+/// Different rolls and branches often come to the same case, e.g. after a die is rerolled,
+/// so it is enough to evaluate it once. Memo is valid for one set of effects only.
+#[derive(Default)]
+struct Memo {
+  effects: Vec<MemoStage>,
+}
+
+#[derive(Default)]
+struct MemoStage {
+  /// Index of the last case remembered with a hash. Hash only picks the slot, as different cases
+  /// could get the same hash, so cases are compared as a whole on lookup.
+  slots: HashMap<u64, usize, BuildHasherDefault<FxHasher>>,
+  cases: Vec<MemoCase>,
+  /// Faces of remembered cases one after another, so that remembering a case doesn't allocate.
+  faces: Vec<(dice::Type, dice::Face)>,
+  lookups: usize,
+  hits: usize,
+}
+
+struct MemoCase {
+  characteristics: Characteristics,
+  faces: Range<usize>,
+  /// Value is known once the case went through the rest of effects.
+  value: Option<Value>,
+}
+
+impl MemoStage {
+  /// Cases which enter some effects almost never repeat, e.g. when previous effects changed
+  /// them in many ways. Remembering them costs more than evaluating them again.
+  fn useful(&self) -> bool {
+    self.lookups < Memo::trial || self.hits * 16 >= self.lookups
+  }
+
+  fn matches(&self, case: &MemoCase, state: &State) -> bool {
+    let faces = &self.faces[case.faces.clone()];
+    case.characteristics == *state.characteristics
+      && faces.len() == state.roll.len()
+      && faces.iter().zip(state.roll.iter()).all(|(&(die, face), rolled)| die == rolled.die && face == rolled.face)
+  }
+}
+
+impl Memo {
+  /// Number of cases remembered per effect. Rolls are evaluated in order, so the same cases
+  /// tend to show up close to each other and older ones could be forgotten.
+  const capacity: usize = 1 << 16;
+  /// Number of lookups after which memo decides whether it is worth to remember cases for an effect.
+  const trial: usize = 1 << 12;
+
+  fn useful(&self, effect_idx: usize) -> bool {
+    self.effects.get(effect_idx).is_none_or(MemoStage::useful)
+  }
+
+  /// Only the roll is hashed, characteristics are rarely changed by effects and hashing them
+  /// takes longer than the rest of a lookup. They are still compared on lookup.
+  fn hash(state: &State) -> u64 {
+    let mut hasher = FxHasher::default();
+    for face in state.roll.iter() {
+      hasher.write_u64((face.die as u64) << 8 | face.face as u64);
+    }
+    hasher.finish()
+  }
+
+  fn get(&mut self, effect_idx: usize, hash: u64, state: &State) -> Option<Value> {
+    if self.effects.len() <= effect_idx {
+      self.effects.resize_with(effect_idx + 1, Default::default);
+    }
+    let stage = &mut self.effects[effect_idx];
+    stage.lookups += 1;
+    let value = stage.slots.get(&hash)
+      .map(|&idx| &stage.cases[idx])
+      .filter(|case| stage.matches(case, state))
+      .and_then(|case| case.value);
+    if value.is_some() {
+      stage.hits += 1;
+    }
+    value
+  }
+
+  /// Remembers the case before it goes through the rest of effects, as it is gone afterwards.
+  /// Returns index of the case to set its value later, unless the case isn't worth remembering.
+  fn remember(&mut self, effect_idx: usize, hash: u64, state: &State) -> Option<usize> {
+    let stage = &mut self.effects[effect_idx];
+    if !stage.useful() {
+      *stage = MemoStage { lookups: stage.lookups, hits: stage.hits, ..Default::default() };
+      return None;
+    }
+    if stage.cases.len() >= Memo::capacity {
+      stage.slots.clear();
+      stage.cases.clear();
+      stage.faces.clear();
+    }
+    let start = stage.faces.len();
+    stage.faces.extend(state.roll.iter().map(|face| (face.die, face.face)));
+    stage.cases.push(MemoCase {
+      characteristics: state.characteristics.as_ref().clone(),
+      faces: start..stage.faces.len(),
+      value: None,
+    });
+    stage.slots.insert(hash, stage.cases.len() - 1);
+    Some(stage.cases.len() - 1)
+  }
+
+  fn set(&mut self, effect_idx: usize, case_idx: usize, value: Value) {
+    self.effects[effect_idx].cases[case_idx].value = Some(value);
+  }
+}
+
+/// Simple and fast hasher, the same as used by rustc. Memo doesn't need protection from
+/// collision attacks, but it is hit for every alteration.
+#[derive(Default)]
+struct FxHasher {
+  hash: u64,
+}
+
+impl Hasher for FxHasher {
+  fn write(&mut self, bytes: &[u8]) {
+    let mut words = bytes.chunks_exact(8);
+    for word in &mut words {
+      let mut buf = [0; 8];
+      buf.copy_from_slice(word);
+      self.write_u64(u64::from_le_bytes(buf));
+    }
+    for &byte in words.remainder() {
+      self.write_u64(byte as u64);
+    }
+  }
+
+  fn write_u8(&mut self, byte: u8) {
+    self.write_u64(byte as u64);
+  }
+
+  fn write_u64(&mut self, word: u64) {
+    self.hash = (self.hash.rotate_left(5) ^ word).wrapping_mul(0x51_7c_c1_b7_27_22_0a_95);
+  }
+
+  fn finish(&self) -> u64 {
+    self.hash
+  }
+}
+
 impl<'a> EffectApplicator<'a> {
+  /// Passes the case further, to the next effect. Effect could yield more alterations afterwards.
   pub fn yield_next_alteration(&mut self, case: &mut Case, prob: Real) {
-    self.probs[self.effect_idx] = prob;
-    if self.effect_idx == self.effects.len() - 1 {
-      self.update_after_last_effect(case);
+    self.trace(case, prob);
+    if case.altered {
+      let state = State::owned(case, &mut self.spares);
+      self.postpone(state, prob);
     } else {
-      let mut case = case.reborrow();
-      self.effect_idx += 1;
-      self.effects[self.effect_idx].yield_alterations(self, &mut case);
-      self.effect_idx -= 1;
+      self.unaltered += prob;
     }
   }
 
   pub fn yield_last_alteration(&mut self, case: &mut Case, prob: Real) -> LastAlteration {
-    self.probs[self.effect_idx] = prob;
-    if self.effect_idx == self.effects.len() - 1 {
-      self.update_after_last_effect(case);
-    } else {
-      self.effect_idx += 1;
-      self.effects[self.effect_idx].yield_alterations(self, case);
-      self.effect_idx -= 1;
+    self.yield_next_alteration(case, prob);
+    LastAlteration { opaque: PhantomData }
+  }
+
+  fn trace(&mut self, case: &Case, prob: Real) {
+    if let Some(ref mut tracer) = self.tracer {
+      let (ref scores_before, ref result_before) = self.entered;
+      tracer.trace(&Trace {
+        effect_idx: self.effect_idx,
+        probability: self.case_probability * prob,
+        scores_before,
+        scores_after: &case.dice_outcome.scores,
+        result_before,
+        result_after: &case.scores,
+      });
+    }
+  }
+
+  /// Yields a branch per each alteration function, applied to the case as it is now.
+  /// Weights are normalised, so they don't have to sum up to 1, but they should be non-negative
+  /// and at least one of them should be positive. Branches with zero weight are skipped.
+  pub fn branch<F>(&mut self, case: &mut Case, branches: &[(Real, F)]) -> LastAlteration
+  where
+    F: Fn(&mut CaseUpdater),
+  {
+    assert!(
+      branches.iter().all(|&(weight, _)| weight.is_finite() && weight >= 0.0),
+      "branch weights should be finite and non-negative",
+    );
+    let total: Real = branches.iter().map(|&(weight, _)| weight).sum();
+    assert!(total > 0.0, "at least one branch should have positive weight");
+    for &(weight, ref alter) in branches.iter().filter(|&&(weight, _)| weight > 0.0) {
+      let mut branch = self.branch_of(case);
+      branch.update(alter);
+      self.yield_branch(branch, weight / total);
+    }
+    LastAlteration { opaque: PhantomData }
+  }
+
+  /// Rerolls dice at given positions of the roll, yielding a branch per each combination of their faces.
+  pub fn reroll(&mut self, case: &mut Case, indices: &[usize]) -> LastAlteration {
+    if indices.is_empty() {
+      return self.yield_last_alteration(case, 1.0);
+    }
+    for combination in 0..dice::reroll_count(case.roll(), indices) {
+      let faces = || dice::rerolled_faces(case.roll(), indices, combination);
+      let mut branch = self.branch_of(case);
+      branch.update(|updater| {
+        for (idx, face) in faces() {
+          updater.set_face(idx, face);
+        }
+      });
+      self.yield_branch(branch, faces().map(|(_, face)| face.probability).product());
     }
     LastAlteration { opaque: PhantomData }
   }
 
-  fn update_after_last_effect(&mut self, case: &Case) {
-    let prob: Real = self.probs.iter().product();
-    update(&mut self.outcome.scores, &case.scores, |sum, part| {
-      *sum += *part * prob;
+  /// Copy of the case to be altered in a branch. Its roll is changed in a spare buffer.
+  fn branch_of<'c>(&mut self, case: &'c Case) -> Case<'c> {
+    let mut branch = case.reborrow();
+    branch.spare_roll = self.spares.rolls.pop().unwrap_or_default();
+    branch
+  }
+
+  /// Yields a branch made by `branch_of`. Its roll is moved along instead of being copied.
+  fn yield_branch(&mut self, branch: Case, prob: Real) {
+    self.trace(&branch, prob);
+    let state = State::taken(branch, &mut self.spares);
+    self.postpone(state, prob);
+  }
+
+  /// Leaves an altered case to go through the rest of effects later.
+  fn postpone(&mut self, state: State<'static>, prob: Real) {
+    self.pending.push(Pending { effect_idx: self.effect_idx + 1, state, probability: self.probability * prob });
+  }
+
+  /// Yields every alteration from the list as a separate branch.
+  /// Probabilities of alterations should sum up to 1.
+  pub fn yield_each(&mut self, case: &mut Case, alterations: &[Alteration]) -> LastAlteration {
+    let (last, rest) = alterations.split_last().expect("there is always at least one alteration");
+    for alteration in rest {
+      case.update(|updater| updater.restore(alteration));
+      self.yield_next_alteration(case, alteration.probability);
+    }
+    case.update(|updater| updater.restore(last));
+    self.yield_last_alteration(case, last.probability)
+  }
+
+  /// Runs the case through the chain of effects and gathers all states it can end up in,
+  /// instead of accumulating their scores. Useful to build effects out of other effects.
+  pub fn alterations(effects: Vec<&dyn Effect>, case: &Case) -> Vec<Alteration> {
+    let mut applicator = EffectApplicator::new(effects);
+    applicator.collected = Some(vec![]);
+    applicator.evaluate(case, 1.0);
+    applicator.collected.unwrap()
+  }
+
+  /// Passes the case through effects. Instead of recursing into the next effect on every yield,
+  /// the case goes on through effects for as long as they leave it as it is, and altered cases
+  /// are left pending on an explicit stack. Values of cases are memoised, if memo is given.
+  fn evaluate(&mut self, case: &Case, roll_probability: Real) -> Value {
+    self.roll_probability = roll_probability;
+    let mut frames = mem::take(&mut self.frames);
+    frames.push(Frame {
+      effect_idx: 0,
+      memo_idx: None,
+      pending: self.pending.len(),
+      probability: 1.0,
+      roll_probability: 1.0,
+      value: Value::default(),
     });
+    self.pass(&mut frames, State::of(case), 0, 1.0);
+    loop {
+      // Case is evaluated once all alterations made from it went through effects.
+      while frames.last().unwrap().pending == self.pending.len() {
+        let frame = frames.pop().unwrap();
+        if let (&mut Some(ref mut memo), Some(case_idx)) = (&mut self.memo, frame.memo_idx) {
+          memo.set(frame.effect_idx, case_idx, frame.value);
+        }
+        match frames.last_mut() {
+          Some(parent) => parent.value.add(frame.probability, &frame.value),
+          None => {
+            self.frames = frames;
+            return frame.value;
+          }
+        }
+      }
+      let Pending { effect_idx, state, probability } = self.pending.pop().unwrap();
+      self.pass(&mut frames, state, effect_idx, probability);
+    }
   }
 
-  fn apply_effects(mut self, characteristics: &Characteristics, roll: &[dice::RollFace]) -> Outcome {
-    let (dice_outcome, scores) = compute_output(characteristics, roll);
-    self.outcome.probability = dice_outcome.probability;
-    if self.effects.len() > 0 {
-      let mut case = Case {
-        characteristics: Cow::Borrowed(characteristics),
-        dice_roll: Cow::Borrowed(roll),
-        dice_outcome,
-        scores
-      };
-      self.effects[self.effect_idx].yield_alterations(&mut self, &mut case);
-    } else {
-      self.outcome.scores = scores;
+  /// Passes the state through effects, starting from the given one, until some effect alters it
+  /// in all branches or it goes through all of them.
+  fn pass(&mut self, frames: &mut Vec<Frame>, state: State, mut effect_idx: usize, mut probability: Real) {
+    loop {
+      let frame = frames.last_mut().unwrap();
+      if effect_idx == self.effects.len() {
+        frame.value.add(probability, &Value { scores: state.scores, mass: 1.0 });
+        let probability = frame.roll_probability * probability;
+        self.collect(state, probability);
+        return;
+      }
+      if let Some(memo) = self.memo.as_mut().filter(|memo| memo.useful(effect_idx)) {
+        let hash = Memo::hash(&state);
+        if let Some(value) = memo.get(effect_idx, hash, &state) {
+          frame.value.add(probability, &value);
+          self.spares.recycle(state);
+          return;
+        }
+        if let Some(case_idx) = memo.remember(effect_idx, hash, &state) {
+          let roll_probability = frame.roll_probability * probability;
+          frames.push(Frame {
+            effect_idx,
+            memo_idx: Some(case_idx),
+            pending: self.pending.len(),
+            probability,
+            roll_probability,
+            value: Value::default(),
+          });
+          probability = 1.0;
+        }
+      }
+      let roll_probability = frames.last().unwrap().roll_probability * probability;
+      let unaltered = self.alter(effect_idx, &state, probability, roll_probability);
+      if unaltered == 0.0 {
+        self.spares.recycle(state);
+        return;
+      }
+      probability *= unaltered;
+      effect_idx += 1;
+    }
+  }
+
+  /// Passes the state through a single effect. Altered cases are left pending,
+  /// and the probability of the case to be left as it was is returned.
+  fn alter(&mut self, effect_idx: usize, state: &State, probability: Real, roll_probability: Real) -> Real {
+    self.effect_idx = effect_idx;
+    self.probability = probability;
+    self.case_probability = self.roll_probability * roll_probability;
+    self.unaltered = 0.0;
+    if self.tracer.is_some() {
+      self.entered = (state.dice_outcome.scores, state.scores);
+    }
+    let effect = self.effects[effect_idx];
+    let mut case = state.case(&mut self.spares);
+    effect.yield_alterations(self, &mut case);
+    self.spares.reclaim(case);
+    self.unaltered
+  }
+
+  fn collect(&mut self, state: State, probability: Real) {
+    match self.collected {
+      Some(ref mut collected) => collected.push(Alteration {
+        characteristics: state.characteristics.into_owned(),
+        roll: state.roll.into_owned(),
+        scores: state.scores,
+        probability,
+      }),
+      None => self.spares.recycle(state),
     }
-    self.outcome
+  }
+
+  fn apply_effects(&mut self, characteristics: &Characteristics, roll: &[dice::RollFace]) -> Outcome {
+    let roll_outcome = roll_outcome(roll);
+    let (dice_outcome, scores) = resolve(characteristics, roll, &roll_outcome);
+    let probability = dice_outcome.probability;
+    let case = Case {
+      characteristics: Cow::Borrowed(characteristics),
+      dice_roll: Cow::Borrowed(roll),
+      roll_outcome,
+      dice_outcome,
+      scores,
+      altered: false,
+      spare_roll: vec![],
+    };
+    let value = self.evaluate(&case, probability);
+    if self.check_conservation {
+      assert!(
+        (value.mass - 1.0).abs() < 1e-9,
+        "probabilities of alterations yielded by effects sum up to {} instead of 1",
+        value.mass,
+      );
+    }
+    Outcome { probability, scores: value.scores }
   }
 
   fn new(effects: Vec<&'a dyn Effect>) -> EffectApplicator<'a> {
-    let len = effects.len();
     EffectApplicator {
       effects,
       effect_idx: 0,
-      outcome: Default::default(),
-      probs: vec![1.0; len],
+      probability: 1.0,
+      unaltered: 0.0,
+      pending: vec![],
+      frames: vec![],
+      spares: Default::default(),
+      memo: None,
+      collected: None,
+      tracer: None,
+      case_probability: 1.0,
+      roll_probability: 1.0,
+      entered: Default::default(),
+      check_conservation: false,
     }
   }
 }
@@ -205,9 +1161,16 @@ pub trait Effect {
   fn yield_alterations<'a, 'b>(&self, collector: &mut EffectApplicator, case: &'a mut Case<'b>) -> LastAlteration;
 }
 
+impl<E: Effect + ?Sized> Effect for &E {
+  fn yield_alterations<'a, 'b>(&self, collector: &mut EffectApplicator, case: &'a mut Case<'b>) -> LastAlteration {
+    (**self).yield_alterations(collector, case)
+  }
+}
+
 #[cfg(test)]
 mod test {
   use attack::*;
+  use std::convert::TryInto;
 
   fn roll(face: dice::Face) -> dice::RollFace {
     dice::RollFace { die: dice::White, face, probability: 0.0 }
@@ -246,10 +1209,13 @@ mod test {
   fn hard_armor_applies() {
     let chars = &Characteristics {
       base_score: dice::new_scores(&[(dice::Damage, 5), (dice::Hit, 1)]),
-      hard_armor: 1,
+      target: Target {
+        physical_armor: Armor { soft_armor: 0, hard_armor: 1 },
+        ..Default::default()
+      },
       ..Default::default()
     };
-    let output = compute_output(&chars, &[]);
+    let output = compute_output(chars, &[]);
     assert_eq!(output.1[Damage].round() as i64, 4);
   }
 
@@ -257,10 +1223,13 @@ mod test {
   fn soft_armor_low_roll() {
     let chars = &Characteristics {
       base_score: dice::new_scores(&[(dice::Damage, 5), (dice::Hit, 1)]),
-      soft_armor: 3,
+      target: Target {
+        physical_armor: Armor { soft_armor: 3, hard_armor: 0 },
+        ..Default::default()
+      },
       ..Default::default()
     };
-    let output = compute_output(&chars, &[roll(dice::Armor2)]);
+    let output = compute_output(chars, &[roll(dice::Armor2)]);
     assert_eq!(output.1[Damage].round() as i64, 3);
   }
 
@@ -268,20 +1237,231 @@ mod test {
   fn soft_armor_high_roll() {
     let chars = &Characteristics {
       base_score: dice::new_scores(&[(dice::Damage, 5), (dice::Hit, 1)]),
-      soft_armor: 3,
+      target: Target {
+        physical_armor: Armor { soft_armor: 3, hard_armor: 0 },
+        ..Default::default()
+      },
+      ..Default::default()
+    };
+    let output = compute_output(chars, &[roll(dice::Armor4)]);
+    assert_eq!(output.1[Damage].round() as i64, 5);
+  }
+
+  #[test]
+  fn armor_dice_are_summed() {
+    let chars = &Characteristics {
+      base_score: dice::new_scores(&[(dice::Damage, 5), (dice::Hit, 1)]),
+      target: Target {
+        physical_armor: Armor { soft_armor: 3, hard_armor: 0 },
+        ..Default::default()
+      },
       ..Default::default()
     };
-    let output = compute_output(&chars, &[roll(dice::Armor4)]);
+    let output = compute_output(chars, &[roll(dice::Armor1), roll(dice::Armor2)]);
+    assert_eq!(output.1[Damage].round() as i64, 2);
+    let output = compute_output(chars, &[roll(dice::Armor1), roll(dice::Armor3)]);
     assert_eq!(output.1[Damage].round() as i64, 5);
   }
 
+  #[test]
+  fn best_armor_die_applies() {
+    let chars = &Characteristics {
+      base_score: dice::new_scores(&[(dice::Damage, 5), (dice::Hit, 1)]),
+      target: Target {
+        physical_armor: Armor { soft_armor: 3, hard_armor: 0 },
+        ..Default::default()
+      },
+      armor_rule: ArmorRule::Best,
+      ..Default::default()
+    };
+    let output = compute_output(chars, &[roll(dice::Armor1), roll(dice::Armor2)]);
+    assert_eq!(output.1[Damage].round() as i64, 3);
+    let output = compute_output(chars, &[roll(dice::Armor1), roll(dice::Armor4)]);
+    assert_eq!(output.1[Damage].round() as i64, 4);
+    let output = compute_output(chars, &[roll(dice::Armor4), roll(dice::Armor4)]);
+    assert_eq!(output.1[Damage].round() as i64, 5);
+  }
+
+  #[test]
+  fn two_red_dice_statistics() {
+    let damage = |armor_rule| Disposition {
+      dice: vec![dice::Red, dice::Red],
+      characteristics: Characteristics {
+        base_score: dice::new_scores(&[(dice::Damage, 5), (dice::Hit, 1)]),
+        target: Target {
+          physical_armor: Armor { soft_armor: 3, hard_armor: 0 },
+          ..Default::default()
+        },
+        armor_rule,
+        ..Default::default()
+      },
+      ..Default::default()
+    }.average_scores()[Damage];
+    // Faces are 1 (4 of 12), 2 (3 of 12), 3 (3 of 12) and 4 (2 of 12).
+    // Summed armour stops damage only for 1 + 1 and 1 + 2.
+    assert_eq!((damage(ArmorRule::Sum) * 144.0).round() as i64, 144 * 5 - 4 * 4 * 2 - 2 * 4 * 3 * 3);
+    // Best die is 4 only if both are 4, otherwise the highest of them up to 3 stops damage.
+    let stopped = (4 * 4 + 2 * 4 * 2) + (3 * 3 + 2 * 3 * 4 + 2 * 3 * 2) * 2 + (144 - 9 * 9) * 3;
+    assert_eq!((damage(ArmorRule::Best) * 144.0).round() as i64, 144 * 5 - stopped);
+  }
+
+  #[test]
+  fn ignored_armor_after_shred() {
+    let chars = &Characteristics {
+      base_score: dice::new_scores(&[(dice::Damage, 5), (dice::Hit, 1), (dice::Shred, 1)]),
+      target: Target {
+        physical_armor: Armor { soft_armor: 3, hard_armor: 2 },
+        ..Default::default()
+      },
+      ignored_soft_armor: 1,
+      ignored_hard_armor: 1,
+      ..Default::default()
+    };
+    let output = compute_output(chars, &[roll(dice::Armor2)]);
+    assert_eq!(output.1[Damage].round() as i64, 3);
+    let output = compute_output(chars, &[roll(dice::Armor3)]);
+    assert_eq!(output.1[Damage].round() as i64, 4);
+  }
+
+  #[test]
+  fn crit_effects_apply_on_hit() {
+    let output = |crit_effect, wounded, face| compute_output(&Characteristics {
+      base_score: dice::new_scores(&[(dice::Damage, 2)]),
+      required_skill: 5,
+      crit_effect,
+      target: Target {
+        physical_armor: Armor { soft_armor: 2, hard_armor: 1 },
+        wounded,
+        ..Default::default()
+      },
+      ..Default::default()
+    }, &[roll(face), roll(dice::Armor2)]).1;
+
+    assert_eq!(output(CritEffect::Maim, false, dice::Crit1)[BrokenLegs], 1.0);
+    assert_eq!(output(CritEffect::Maim, false, dice::Skill5)[BrokenLegs], 0.0);
+    assert_eq!(output(CritEffect::ExtraDamage(2), false, dice::Crit1)[Damage], 1.0);
+    assert_eq!(output(CritEffect::IgnoreArmor, false, dice::Crit1)[Damage], 2.0);
+    assert_eq!(output(CritEffect::IgnoreArmor, false, dice::Skill5)[Damage], 0.0);
+    assert_eq!(output(CritEffect::KillWounded, true, dice::Crit1)[Kills], 1.0);
+    assert_eq!(output(CritEffect::KillWounded, false, dice::Crit1)[Kills], 0.0);
+  }
+
+  #[test]
+  fn explosion_adds_damage_on_hit() {
+    let chars = &Characteristics {
+      base_score: dice::new_scores(&[(dice::Damage, 1)]),
+      required_skill: 5,
+      explosion_damage: 2,
+      ..Default::default()
+    };
+    let output = compute_output(chars, &[roll(dice::Skill5), roll(dice::Explosion1), roll(dice::Armor2)]);
+    assert_eq!(output.1[Damage], 3.0);
+    let output = compute_output(chars, &[roll(dice::Skill6), roll(dice::Explosion1), roll(dice::Armor2)]);
+    assert_eq!(output.1[Damage], 0.0);
+  }
+
+  #[test]
+  fn blast_damage_per_target() {
+    let disposition = Disposition {
+      dice: vec![dice::Blue, dice::Red],
+      characteristics: Characteristics {
+        base_score: dice::new_scores(&[(dice::Hit, 1)]),
+        explosion_damage: 2,
+        ..Default::default()
+      },
+      blast_targets: vec![
+        Armor { soft_armor: 0, hard_armor: 1 },
+        Armor::default(),
+        Armor { soft_armor: 2, hard_armor: 0 },
+      ],
+      ..Default::default()
+    };
+    let damage = disposition.blast_damage();
+    assert_eq!((damage[0] * 12.0).round() as i64, 2);
+    assert_eq!((damage[1] * 12.0).round() as i64, 4);
+    // Red die of the roll stops 1 damage on 4 of 12 faces and all of it on 3 of 12 faces.
+    assert_eq!((damage[2] * 144.0).round() as i64, 2 * (4 + 5 * 2));
+    let missed = Disposition {
+      characteristics: Characteristics { required_skill: -1, explosion_damage: 2, ..Default::default() },
+      ..disposition
+    };
+    assert_eq!(missed.blast_damage(), vec![0.0; 3]);
+  }
+
+  #[test]
+  fn armor_matches_damage_type() {
+    let target = |damage_type| Characteristics {
+      base_score: dice::new_scores(&[(dice::Damage, 4), (dice::Hit, 1)]),
+      damage_type,
+      target: Target {
+        physical_armor: Armor { soft_armor: 0, hard_armor: 3 },
+        energy_armor: Armor { soft_armor: 0, hard_armor: 1 },
+        fire_armor: Armor { soft_armor: 2, hard_armor: 0 },
+        ..Default::default()
+      },
+      ..Default::default()
+    };
+    let output = compute_output(&target(DamageType::Physical), &[roll(dice::Armor2)]);
+    assert_eq!((output.1[Damage], output.1[PhysicalDamage]), (1.0, 1.0));
+    let output = compute_output(&target(DamageType::Energy), &[roll(dice::Armor2)]);
+    assert_eq!((output.1[Damage], output.1[EnergyDamage], output.1[PhysicalDamage]), (3.0, 3.0, 0.0));
+    let output = compute_output(&target(DamageType::Radiation), &[roll(dice::Armor2)]);
+    assert_eq!((output.1[Damage], output.1[RadiationDamage]), (4.0, 4.0));
+    let output = compute_output(&target(DamageType::Fire), &[roll(dice::Armor2)]);
+    assert_eq!((output.1[Damage], output.1[FireDamage]), (2.0, 2.0));
+  }
+
+  #[test]
+  fn radiation_reaches_thresholds() {
+    let output = |rad, hp: Option<Score>, face| compute_output(&Characteristics {
+      base_score: dice::new_scores(&[(dice::Rad, 3), (dice::Damage, 1)]),
+      required_skill: 5,
+      target: Target {
+        rad_resistance: 1,
+        rad,
+        rad_threshold: Some(3.try_into().unwrap()),
+        hp: hp.map(|hp| hp.try_into().unwrap()),
+        ..Default::default()
+      },
+      ..Default::default()
+    }, &[roll(face)]).1;
+
+    let hit = output(0, None, dice::Skill5);
+    assert_eq!((hit[Radiation], hit[Damage], hit[RadiationDamage]), (2.0, 1.0, 0.0));
+    let hit = output(1, None, dice::Skill5);
+    assert_eq!((hit[Radiation], hit[Damage], hit[RadiationDamage]), (2.0, 2.0, 1.0));
+    // Damage of the hit takes the last hit point, so HP lost to radiation is wasted.
+    let kill = output(1, Some(1), dice::Skill5);
+    assert_eq!((kill[Damage], kill[PhysicalDamage], kill[RadiationDamage]), (1.0, 1.0, 0.0));
+    assert_eq!((kill[WastedDamage], kill[Kills]), (1.0, 1.0));
+    let miss = output(1, None, dice::Skill6);
+    assert_eq!((miss[Radiation], miss[Damage]), (0.0, 0.0));
+  }
+
+  #[test]
+  fn damage_beyond_hp_is_wasted() {
+    let output = |target_hp: Score| compute_output(&Characteristics {
+      base_score: dice::new_scores(&[(dice::Damage, 3), (dice::Hit, 1)]),
+      target: Target::test_hp(target_hp),
+      ..Default::default()
+    }, &[roll(dice::Damage2)]).1;
+
+    let overkill = output(2);
+    assert_eq!((overkill[Damage], overkill[WastedDamage], overkill[Kills]), (2.0, 3.0, 1.0));
+    assert_eq!(overkill[PhysicalDamage], 2.0);
+    let exact = output(5);
+    assert_eq!((exact[Damage], exact[WastedDamage], exact[Kills]), (5.0, 0.0, 1.0));
+    let wound = output(6);
+    assert_eq!((wound[Damage], wound[WastedDamage], wound[Kills]), (5.0, 0.0, 0.0));
+  }
+
   #[test]
   fn damage_is_summed() {
     let chars = &Characteristics {
       base_score: dice::new_scores(&[(dice::Damage, 5), (dice::Hit, 1)]),
       ..Default::default()
     };
-    let output = compute_output(&chars, &[roll(dice::Damage1), roll(dice::Damage2)]);
+    let output = compute_output(chars, &[roll(dice::Damage1), roll(dice::Damage2)]);
     assert_eq!(output.1[Damage].round() as i64, 8);
   }
 
@@ -289,10 +1469,13 @@ mod test {
   fn shred_ignores_armor() {
     let chars = &Characteristics {
       base_score: dice::new_scores(&[(dice::Damage, 5), (dice::Hit, 1)]),
-      soft_armor: 3,
+      target: Target {
+        physical_armor: Armor { soft_armor: 3, hard_armor: 0 },
+        ..Default::default()
+      },
       ..Default::default()
     };
-    let output = compute_output(&chars, &[roll(dice::Armor3), roll(dice::Shred1)]);
+    let output = compute_output(chars, &[roll(dice::Armor3), roll(dice::Shred1)]);
     assert_eq!(output.1[Damage].round() as i64, 5);
   }
 
@@ -316,7 +1499,10 @@ mod test {
     let disposition = Disposition {
       characteristics: Characteristics {
         base_score: dice::new_scores(&[(dice::Damage, 3), (dice::Hit, 1)]),
-        soft_armor: 2,
+        target: Target {
+          physical_armor: Armor { soft_armor: 2, hard_armor: 0 },
+          ..Default::default()
+        },
         ..Default::default()
       },
       dice: vec![dice::Red],
@@ -326,6 +1512,230 @@ mod test {
     assert_eq!((outcome[Damage] * 12.0).round() as i64,  26);
   }
 
+  struct SkillSpread {}
+
+  impl Effect for SkillSpread {
+    fn yield_alterations(&self, applicator: &mut EffectApplicator, case: &mut Case) -> LastAlteration {
+      let branches = [(3.0, -1), (0.0, 10), (1.0, 1)]
+        .iter()
+        .map(|&(weight, skill)| (weight, move |updater: &mut CaseUpdater| *updater.score_mut(dice::Skill) += skill))
+        .collect::<Vec<_>>();
+      applicator.branch(case, &branches)
+    }
+  }
+
+  #[test]
+  fn branch_weights_are_normalised() {
+    let spread = &SkillSpread{};
+    let disposition = Disposition {
+      characteristics: Characteristics {
+        base_score: dice::new_scores(&[(dice::Skill, 6), (dice::Damage, 4)]),
+        required_skill: 6,
+        ..Default::default()
+      },
+      effects: vec![spread],
+      ..Default::default()
+    };
+    let outcome = disposition.average_scores();
+    assert_eq!((outcome[Damage] * 1e9).round() as i64, 3_000_000_000);
+  }
+
+  struct NegativeBranch {}
+
+  impl Effect for NegativeBranch {
+    fn yield_alterations(&self, applicator: &mut EffectApplicator, case: &mut Case) -> LastAlteration {
+      applicator.branch(case, &[(-1.0, |_: &mut CaseUpdater| ()); 2])
+    }
+  }
+
+  #[test]
+  #[should_panic(expected = "non-negative")]
+  fn negative_branch_weight_panics() {
+    let negative = &NegativeBranch{};
+    let disposition = Disposition {
+      effects: vec![negative],
+      ..Default::default()
+    };
+    disposition.average_scores();
+  }
+
+  struct ForgottenBranch {}
+
+  impl Effect for ForgottenBranch {
+    fn yield_alterations(&self, applicator: &mut EffectApplicator, case: &mut Case) -> LastAlteration {
+      applicator.yield_last_alteration(case, 0.5)
+    }
+  }
+
+  #[test]
+  #[should_panic(expected = "instead of 1")]
+  fn forgotten_branch_is_detected() {
+    let forgotten = &ForgottenBranch{};
+    let disposition = Disposition {
+      dice: vec![dice::Black],
+      effects: vec![forgotten],
+      check_conservation: true,
+      ..Default::default()
+    };
+    disposition.average_scores();
+  }
+
+  #[test]
+  fn builtin_effects_conserve_probability() {
+    let rbb = &::effects::RerollBlackBlank{};
+    let rab = &::effects::RerollAnyBlank{};
+    let hit_luck = &::effects::LuckForHit{};
+    let armor_luck = &::effects::LuckForArmor{};
+    let disposition = Disposition {
+      dice: vec![dice::Red, dice::White, dice::Black, dice::Green],
+      characteristics: Characteristics {
+        base_score: dice::new_scores(&[(dice::Damage, 1)]),
+        required_skill: 6,
+        target: Target {
+          physical_armor: Armor { soft_armor: 2, hard_armor: 0 },
+          ..Default::default()
+        },
+        ..Default::default()
+      },
+      effects: vec![rbb, rab, hit_luck, armor_luck],
+      check_conservation: true,
+      ..Default::default()
+    };
+    disposition.average_scores();
+  }
+
+  #[test]
+  fn explain_luck_for_hit() {
+    let luck = &::effects::LuckForHit{};
+    let disposition = Disposition {
+      dice: vec![dice::White],
+      characteristics: Characteristics {
+        required_skill: 6,
+        base_score: dice::new_scores(&[(dice::Damage, 1)]),
+        ..Default::default()
+      },
+      effects: vec![luck],
+      ..Default::default()
+    };
+    let reports = disposition.explain();
+    assert_eq!(reports.len(), 1);
+    assert_eq!((reports[0].trigger_probability * 20.0).round() as i64, 2);
+    assert_eq!((reports[0].contribution[Damage] * 20.0).round() as i64, 2);
+    assert_eq!((reports[0].contribution[Hits] * 20.0).round() as i64, 2);
+  }
+
+  #[test]
+  fn trace_passes_every_branch() {
+    let luck = &::effects::LuckForHit{};
+    let disposition = Disposition {
+      dice: vec![dice::White],
+      characteristics: Characteristics {
+        required_skill: 6,
+        ..Default::default()
+      },
+      effects: vec![luck],
+      ..Default::default()
+    };
+    let mut total = 0.0;
+    disposition.trace(&mut |trace: &Trace| total += trace.probability);
+    assert_eq!((total * 1e9).round() as i64, 1_000_000_000);
+  }
+
+  #[test]
+  fn memo_keeps_results() {
+    let rbb = &::effects::RerollBlackBlank{};
+    let rab = &::effects::RerollAnyBlank{};
+    let hit_luck = &::effects::LuckForHit{};
+    let crit_luck = &::effects::LuckForCrit{};
+    let disposition = Disposition {
+      dice: vec![dice::Red, dice::Black, dice::Black, dice::Green],
+      characteristics: Characteristics {
+        base_score: dice::new_scores(&[(dice::Damage, 1)]),
+        required_skill: 7,
+        target: Target {
+          physical_armor: Armor { soft_armor: 1, hard_armor: 0 },
+          ..Default::default()
+        },
+        ..Default::default()
+      },
+      effects: vec![rbb, rab, hit_luck, crit_luck],
+      ..Default::default()
+    };
+    let plain = disposition.dice
+      .iter()
+      .map(|&die| dice::RollFace::faces_of(die).iter().cloned())
+      .cartesian_collections(|roll| {
+        EffectApplicator::new(disposition.effects.clone()).apply_effects(&disposition.characteristics, roll)
+      })
+      .fold(Scores::default(), |mut acc, next| {
+        update(&mut acc, &next.scores, |sum, part| *sum += *part * next.probability);
+        acc
+      });
+    let memoised = disposition.average_scores();
+    for (facet, value) in plain.iter() {
+      assert_eq!((value * 1e9).round(), (memoised[facet] * 1e9).round());
+    }
+  }
+
+  #[test]
+  fn set_face_matches_summing_up() {
+    let characteristics = Characteristics {
+      base_score: dice::new_scores(&[(dice::Damage, 1)]),
+      required_skill: 7,
+      target: Target {
+        physical_armor: Armor { soft_armor: 2, hard_armor: 0 },
+        ..Default::default()
+      },
+      ..Default::default()
+    };
+    let roll = [dice::White, dice::Red, dice::Green, dice::Black, dice::Yellow, dice::Blue]
+      .iter()
+      .map(|&die| dice::RollFace::faces_of(die)[0])
+      .collect::<Vec<_>>();
+    let roll_outcome = roll_outcome(&roll);
+    let (dice_outcome, scores) = resolve(&characteristics, &roll, &roll_outcome);
+    let mut case = Case {
+      characteristics: Cow::Borrowed(&characteristics),
+      dice_roll: Cow::Borrowed(&roll),
+      roll_outcome,
+      dice_outcome,
+      scores,
+      altered: false,
+      spare_roll: vec![],
+    };
+    for (idx, rolled) in roll.iter().enumerate() {
+      for &face in dice::RollFace::faces_of(rolled.die) {
+        case.update(|updater| updater.set_face(idx, face));
+        let (expected, results) = compute_output(&characteristics, case.roll());
+        assert!(case.dice_outcome.scores == expected.scores);
+        assert!(case.scores == results);
+      }
+    }
+  }
+
+  #[test]
+  fn faces_without_probability_can_be_changed() {
+    let characteristics = Characteristics { required_skill: 6, ..Default::default() };
+    let rolled = [roll(dice::Skill7), roll(dice::Miss1)];
+    let roll_outcome = roll_outcome(&rolled);
+    let (dice_outcome, scores) = resolve(&characteristics, &rolled, &roll_outcome);
+    let mut case = Case {
+      characteristics: Cow::Borrowed(&characteristics),
+      dice_roll: Cow::Borrowed(&rolled),
+      roll_outcome,
+      dice_outcome,
+      scores,
+      altered: false,
+      spare_roll: vec![],
+    };
+    case.update(|updater| updater.set_face(0, dice::RollFace::faces_of(dice::White)[2]));
+    assert_eq!(case.dice_outcome.probability, 0.0);
+    assert_eq!(case.score(dice::Skill), 2);
+    case.update(|updater| updater.roll_mut()[1] = dice::RollFace::faces_of(dice::White)[3]);
+    assert_eq!(case.dice_outcome.probability, 0.05 * 0.05);
+    assert_eq!((case.score(dice::Skill), case.result(Hits)), (5, 1.0));
+  }
+
   #[test]
   fn black_2_dice_statistics() {
     let disposition = Disposition {
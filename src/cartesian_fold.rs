@@ -10,7 +10,7 @@ where
   fn cartesian_collections<F, R>(self, f: F) -> CartesianProductCollections<I, F>
   where
     Self::Item: Iterator + Clone,
-    F: Fn(&'_ [<<Self as Iterator>::Item as Iterator>::Item]) -> R,
+    F: FnMut(&'_ [<<Self as Iterator>::Item as Iterator>::Item]) -> R,
   {
     CartesianProductCollections::<I, F>::new(self, f)
   }
@@ -42,7 +42,7 @@ impl<I, F, R> Iterator for CartesianProductCollections<I, F>
 where
   I: Iterator,
   I::Item: Iterator + Clone,
-  F: Fn(&'_ [<<I as Iterator>::Item as Iterator>::Item]) -> R,
+  F: FnMut(&'_ [<<I as Iterator>::Item as Iterator>::Item]) -> R,
 {
   type Item = R;
 
@@ -115,7 +115,7 @@ pub trait IterExt: Iterator + Sized {
   fn cartesian_collections<F, R>(self, f: F) -> CartesianProductCollections<Self, F>
   where
     Self::Item: Iterator + Clone,
-    F: Fn(&'_ [<<Self as Iterator>::Item as Iterator>::Item]) -> R;
+    F: FnMut(&'_ [<<Self as Iterator>::Item as Iterator>::Item]) -> R;
 }
 
 #[test]
@@ -131,7 +131,7 @@ fn numbers_from_digits() {
 
 #[test]
 fn if_one_iter_is_empty_result_is_empty() {
-  let numbers = vec![vec![1, 2], vec![], vec![1, 2, 3]];
+  let numbers = [vec![1, 2], vec![], vec![1, 2, 3]];
   assert_eq!(
     numbers
       .iter()
@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+use common::*;
+use attack;
+use distribution::{ Distribution, whole };
+
+/// What scored actions are spent on.
+#[derive(Clone, Copy)]
+pub enum ActionUse<'d, 'a> {
+  /// Every action is a follow-up attack with given disposition, which may score actions again.
+  Attack(&'d attack::Disposition<'a>),
+  /// Every action is spent on something else, worth given damage.
+  /// Worth is whole and non-negative, the same as damage in the distribution.
+  Value(usize),
+}
+
+/// An attack followed by more attacks or other actions paid with actions it scored.
+pub struct ActionChain<'d, 'a> {
+  pub first: &'d attack::Disposition<'a>,
+  pub actions: ActionUse<'d, 'a>,
+  /// Limit of attacks in the chain, the first one included. Actions left after that are lost.
+  pub max_attacks: usize,
+}
+
+impl<'d, 'a> ActionChain<'d, 'a> {
+  /// Distribution of damage dealt by the whole chain.
+  pub fn damage(&self) -> Distribution {
+    let first = damage_and_actions(self.first);
+    let mut damage = Distribution::default();
+    match self.actions {
+      ActionUse::Value(value) => {
+        for (&(dealt, actions), &probability) in &first {
+          damage.add(dealt + actions * value, probability);
+        }
+      }
+      ActionUse::Attack(follow_up) => {
+        let mut follow_ups = FollowUps {
+          outcomes: damage_and_actions(follow_up),
+          damage: HashMap::new(),
+        };
+        let attacks_left = self.max_attacks.saturating_sub(1);
+        for (&(dealt, actions), &probability) in &first {
+          let rest = follow_ups.damage(actions.min(attacks_left), attacks_left);
+          damage.add_scaled(&rest.shifted(dealt), probability);
+        }
+      }
+    }
+    damage
+  }
+}
+
+/// Joint distribution of damage and actions scored by a single attack.
+fn damage_and_actions(disposition: &attack::Disposition) -> HashMap<(usize, usize), Real> {
+  let mut outcomes = HashMap::new();
+  disposition.outcomes(|scores, probability| {
+    let key = (whole(scores[attack::Damage]), whole(scores[attack::Actions]));
+    *outcomes.entry(key).or_insert(0.0) += probability;
+  });
+  outcomes
+}
+
+struct FollowUps {
+  outcomes: HashMap<(usize, usize), Real>,
+  /// Distributions of damage of pending attacks, by their number and limit of attacks left.
+  damage: HashMap<(usize, usize), Distribution>,
+}
+
+impl FollowUps {
+  fn damage(&mut self, pending: usize, attacks_left: usize) -> Distribution {
+    if pending == 0 {
+      return Distribution::certain(0);
+    }
+    if let Some(damage) = self.damage.get(&(pending, attacks_left)) {
+      return damage.clone();
+    }
+    let mut damage = Distribution::default();
+    let outcomes = self.outcomes.iter().map(|(&key, &probability)| (key, probability)).collect::<Vec<_>>();
+    for ((dealt, actions), probability) in outcomes {
+      let pending = (pending - 1 + actions).min(attacks_left - 1);
+      let rest = self.damage(pending, attacks_left - 1);
+      damage.add_scaled(&rest.shifted(dealt), probability);
+    }
+    self.damage.insert((pending, attacks_left), damage.clone());
+    damage
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use chain::*;
+  use dice;
+
+  /// Always hits for 1 damage. White die scores an action on 2 of 20 faces.
+  fn disposition() -> attack::Disposition<'static> {
+    let mut disposition = attack::Disposition::test_shot(vec![dice::White], vec![]);
+    disposition.characteristics.base_score[dice::Hit] = 1;
+    disposition
+  }
+
+  #[test]
+  fn actions_have_value() {
+    let disposition = disposition();
+    let actions = disposition.distribution(attack::Actions);
+    let chain = ActionChain { first: &disposition, actions: ActionUse::Value(3), max_attacks: 1 };
+    let damage = chain.damage();
+    assert!((damage.total() - 1.0).abs() < 1e-9);
+    assert!((damage.probability(4) - actions.probability(1)).abs() < 1e-9);
+  }
+
+  #[test]
+  fn follow_ups_are_geometric() {
+    let disposition = disposition();
+    let action = disposition.distribution(attack::Actions).probability(1);
+    let chain = |max_attacks| ActionChain {
+      first: &disposition,
+      actions: ActionUse::Attack(&disposition),
+      max_attacks,
+    }.damage();
+    let limited = chain(3);
+    assert!((limited.probability(1) - (1.0 - action)).abs() < 1e-9);
+    assert!((limited.probability(2) - action * (1.0 - action)).abs() < 1e-9);
+    assert!((limited.probability(3) - action * action).abs() < 1e-9);
+    assert!((chain(50).mean() - 1.0 / (1.0 - action)).abs() < 1e-9);
+  }
+}
@@ -0,0 +1,180 @@
+use attack;
+use common::*;
+
+pub trait Predicate {
+  fn holds(&self, case: &attack::Case) -> bool;
+}
+
+impl<F> Predicate for F
+where
+  F: Fn(&attack::Case) -> bool,
+{
+  fn holds(&self, case: &attack::Case) -> bool {
+    self(case)
+  }
+}
+
+/// Applies effect only if predicate holds for the case.
+pub struct When<P, E>(pub P, pub E);
+
+impl<P: Predicate, E: attack::Effect> attack::Effect for When<P, E> {
+  fn yield_alterations(
+    &self,
+    applicator: &mut attack::EffectApplicator,
+    case: &mut attack::Case,
+  ) -> attack::LastAlteration {
+    if self.0.holds(case) {
+      self.1.yield_alterations(applicator, case)
+    } else {
+      applicator.yield_last_alteration(case, 1.0)
+    }
+  }
+}
+
+/// Applies all effects one after another, as if they were listed in disposition directly.
+pub struct Sequence<'a>(pub Vec<&'a dyn attack::Effect>);
+
+impl<'a> attack::Effect for Sequence<'a> {
+  fn yield_alterations(
+    &self,
+    applicator: &mut attack::EffectApplicator,
+    case: &mut attack::Case,
+  ) -> attack::LastAlteration {
+    let alterations = attack::EffectApplicator::alterations(self.0.clone(), case);
+    applicator.yield_each(case, &alterations)
+  }
+}
+
+/// Applies the single effect which gives the best expected result.
+/// Results are compared facet by facet in order of their declaration, so damage goes first.
+pub struct BestOf<'a>(pub Vec<&'a dyn attack::Effect>);
+
+impl<'a> attack::Effect for BestOf<'a> {
+  fn yield_alterations(
+    &self,
+    applicator: &mut attack::EffectApplicator,
+    case: &mut attack::Case,
+  ) -> attack::LastAlteration {
+    let best = self.0.iter()
+      .map(|&effect| attack::EffectApplicator::alterations(vec![effect], case))
+      .map(|alterations| (expected_scores(&alterations), alterations))
+      .fold(None, |best: Option<(attack::Scores, Vec<attack::Alteration>)>, next| match best {
+        Some(best) if !is_better(&next.0, &best.0) => Some(best),
+        _ => Some(next),
+      });
+    match best {
+      Some((_, alterations)) => applicator.yield_each(case, &alterations),
+      None => applicator.yield_last_alteration(case, 1.0),
+    }
+  }
+}
+
+/// Applies the same effect several times in a row.
+pub struct Times<E>(pub PositiveScore, pub E);
+
+impl<E: attack::Effect> attack::Effect for Times<E> {
+  fn yield_alterations(
+    &self,
+    applicator: &mut attack::EffectApplicator,
+    case: &mut attack::Case,
+  ) -> attack::LastAlteration {
+    let effects = vec![&self.1 as &dyn attack::Effect; self.0.get() as usize];
+    let alterations = attack::EffectApplicator::alterations(effects, case);
+    applicator.yield_each(case, &alterations)
+  }
+}
+
+fn expected_scores(alterations: &[attack::Alteration]) -> attack::Scores {
+  alterations.iter().fold(attack::Scores::default(), |mut acc, alteration| {
+    attack::update(&mut acc, &alteration.scores, |sum, part| {
+      *sum += *part * alteration.probability;
+    });
+    acc
+  })
+}
+
+/// Compares results facet by facet in order of their declaration.
+pub fn is_better(lhs: &attack::Scores, rhs: &attack::Scores) -> bool {
+  lhs.values()
+    .zip(rhs.values())
+    .find(|(l, r)| l != r)
+    .is_some_and(|(l, r)| l > r)
+}
+
+#[cfg(test)]
+mod test {
+  use combinators::*;
+  use effects;
+  use dice;
+  use std::convert::TryInto;
+
+  #[test]
+  fn when_false_does_nothing() {
+    let luck = &effects::LuckForHit{};
+    let effect = &When(|_: &attack::Case| false, luck);
+    let outcome = attack::Disposition::test_shot(vec![dice::White], vec![effect]).average_scores();
+
+    assert_eq!((outcome[attack::Damage] * 20.0).round() as i64, 11);
+  }
+
+  #[test]
+  fn when_true_applies_effect() {
+    let luck = &effects::LuckForHit{};
+    let effect = &When(|case: &attack::Case| case.score(dice::Skill) > 0, luck);
+    let outcome = attack::Disposition::test_shot(vec![dice::White], vec![effect]).average_scores();
+
+    assert_eq!((outcome[attack::Damage] * 20.0).round() as i64, 13);
+  }
+
+  #[test]
+  fn sequence_is_same_as_listing_effects() {
+    let rbb = &effects::RerollBlackBlank{};
+    let luck = &effects::LuckForHit{};
+    let crit = &effects::LuckForCrit{};
+    let sequence = &Sequence(vec![rbb, luck]);
+    let dice = vec![dice::White, dice::Black];
+    let listed = attack::Disposition::test_shot(dice.clone(), vec![rbb, luck, crit]).average_scores();
+    let sequenced = attack::Disposition::test_shot(dice, vec![sequence, crit]).average_scores();
+
+    assert_eq!((listed[attack::Damage] * 1e9).round(), (sequenced[attack::Damage] * 1e9).round());
+    assert_eq!((listed[attack::Crits] * 1e9).round(), (sequenced[attack::Crits] * 1e9).round());
+  }
+
+  #[test]
+  fn best_of_picks_more_damage() {
+    let damage_on_bottle = &effects::ExchangeScoreOnHit {
+      give: (dice::Bottle, 1.try_into().unwrap()),
+      take: (dice::Damage, 1.try_into().unwrap()),
+      times: None,
+    };
+    let crit_on_bottle = &effects::ExchangeScoreOnHit {
+      give: (dice::Bottle, 1.try_into().unwrap()),
+      take: (dice::Crit, 1.try_into().unwrap()),
+      times: None,
+    };
+    let effect = &BestOf(vec![crit_on_bottle, damage_on_bottle]);
+    let chosen = attack::Disposition::test_shot(vec![dice::White, dice::Blue], vec![effect]).average_scores();
+    let damage = attack::Disposition::test_shot(vec![dice::White, dice::Blue], vec![damage_on_bottle]).average_scores();
+
+    assert_eq!((chosen[attack::Damage] * 1e9).round(), (damage[attack::Damage] * 1e9).round());
+    assert_eq!(chosen[attack::Crits], damage[attack::Crits]);
+  }
+
+  #[test]
+  fn times_rerolls_twice() {
+    let rbb = &effects::RerollBlackBlank{};
+    let effect = &Times(2.try_into().unwrap(), rbb);
+    let disposition = attack::Disposition {
+      dice: vec![dice::Black],
+      characteristics: attack::Characteristics {
+        base_score: dice::new_scores(&[(dice::Hit, 1)]),
+        ..Default::default()
+      },
+      effects: vec![effect],
+      ..Default::default()
+    };
+    let outcome = disposition.average_scores();
+
+    assert_eq!((outcome[attack::Damage] * 144.0 * 12.0).round() as i64, 7 * 12 * 12 + 7 * 3 * 12 + 7 * 3 * 3);
+  }
+}
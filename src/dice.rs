@@ -14,9 +14,10 @@ outcome! {
   Action,
   Hit,
   Miss,
+  Rad,
 }
 
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Type {
   White,
   Red,
@@ -28,6 +29,10 @@ pub enum Type {
 
 pub use self::Type::*;
 
+/// Dice the attacker can add to the pool. There is a single White skill die,
+/// and Red dice are rolled for the defender.
+pub static pool_types: &[Type] = &[Green, Yellow, Black, Blue];
+
 #[derive(Copy, Clone, PartialEq)]
 pub enum Face {
   Skill2,
@@ -76,53 +81,72 @@ impl RollFace {
 
   pub fn add_score(&self, outcome: &mut ::dice::Outcome) {
     outcome.probability *= self.probability;
+    self.change_scores(&mut outcome.scores, 1);
+  }
+
+  /// Scores of the face alone.
+  pub fn scores(&self) -> Scores {
+    let mut scores = Scores::default();
+    self.change_scores(&mut scores, 1);
+    scores
+  }
+
+  /// Takes the face back out of the outcome it was added to.
+  /// Probability of the face should not be 0, as it can't be divided out of the outcome then.
+  pub fn remove_score(&self, outcome: &mut ::dice::Outcome) {
+    outcome.probability /= self.probability;
+    self.change_scores(&mut outcome.scores, -1);
+  }
+
+  fn change_scores(&self, scores: &mut Scores, sign: Score) {
     match self.face {
-      Skill2 => outcome.scores[Facet::Skill] += 2,
-      Skill3 => outcome.scores[Facet::Skill] += 3,
-      Skill4 => outcome.scores[Facet::Skill] += 4,
-      Skill5 => outcome.scores[Facet::Skill] += 5,
-      Skill6 => outcome.scores[Facet::Skill] += 6,
-      Skill7 => outcome.scores[Facet::Skill] += 7,
-      Skill8 => outcome.scores[Facet::Skill] += 8,
-      Skill9 => outcome.scores[Facet::Skill] += 9,
-      Skill10 => outcome.scores[Facet::Skill] += 10,
-      SkillMinus1 => outcome.scores[Facet::Skill] -= 1,
-      SkillMinus2 => outcome.scores[Facet::Skill] -= 2,
-      SkillMinus3 => outcome.scores[Facet::Skill] -= 3,
-      Blank => match self.die {
-        Green => outcome.scores[Facet::Skill] -= 2,
-        _ => (),
+      Skill2 => scores[Facet::Skill] += 2 * sign,
+      Skill3 => scores[Facet::Skill] += 3 * sign,
+      Skill4 => scores[Facet::Skill] += 4 * sign,
+      Skill5 => scores[Facet::Skill] += 5 * sign,
+      Skill6 => scores[Facet::Skill] += 6 * sign,
+      Skill7 => scores[Facet::Skill] += 7 * sign,
+      Skill8 => scores[Facet::Skill] += 8 * sign,
+      Skill9 => scores[Facet::Skill] += 9 * sign,
+      Skill10 => scores[Facet::Skill] += 10 * sign,
+      SkillMinus1 => scores[Facet::Skill] -= sign,
+      SkillMinus2 => scores[Facet::Skill] -= 2 * sign,
+      SkillMinus3 => scores[Facet::Skill] -= 3 * sign,
+      Blank => if self.die == Green {
+        scores[Facet::Skill] -= 2 * sign;
       },
-      Damage1 => outcome.scores[Facet::Damage] += 1,
-      Damage2 => outcome.scores[Facet::Damage] += 2,
-      Shred1 => outcome.scores[Facet::Shred] += 1,
-      Shred2 => outcome.scores[Facet::Shred] += 2,
-      Bottle1 => outcome.scores[Facet::Bottle] += 1,
-      Bottle2 => outcome.scores[Facet::Bottle] += 2,
-      Star1 => outcome.scores[Facet::Star] += 1,
-      Star2 => outcome.scores[Facet::Star] += 2,
+      Damage1 => scores[Facet::Damage] += sign,
+      Damage2 => scores[Facet::Damage] += 2 * sign,
+      Shred1 => scores[Facet::Shred] += sign,
+      Shred2 => scores[Facet::Shred] += 2 * sign,
+      Bottle1 => scores[Facet::Bottle] += sign,
+      Bottle2 => scores[Facet::Bottle] += 2 * sign,
+      Star1 => scores[Facet::Star] += sign,
+      Star2 => scores[Facet::Star] += 2 * sign,
       BottleStar => {
-        outcome.scores[Facet::Bottle] += 1;
-        outcome.scores[Facet::Star] += 1;
+        scores[Facet::Bottle] += sign;
+        scores[Facet::Star] += sign;
       },
-      Explosion1 => outcome.scores[Facet::Explosion] += 1,
+      Explosion1 => scores[Facet::Explosion] += sign,
       Crit1 => {
-        outcome.scores[Facet::Crit] += 1;
-        outcome.scores[Facet::Hit] += 1;
+        scores[Facet::Crit] += sign;
+        scores[Facet::Hit] += sign;
       }
       Action1 => {
-        outcome.scores[Facet::Action] += 1;
-        outcome.scores[Facet::Skill] += 1;
+        scores[Facet::Action] += sign;
+        scores[Facet::Skill] += sign;
       }
-      Miss1 => outcome.scores[Facet::Miss] = 1,
+      // Misses are counted rather than flagged, so that a miss face can be taken back out.
+      // Any number of them above 0 means a miss.
+      Miss1 => scores[Facet::Miss] += sign,
       MissAction => {
-        outcome.scores[Facet::Action] += 1;
-        outcome.scores[Facet::Miss] = 1;
+        scores[Facet::Action] += sign;
+        scores[Facet::Miss] += sign;
       }
-      Armor1 => outcome.scores[Facet::Armor] += 1,
-      Armor2 => outcome.scores[Facet::Armor] += 2,
-      Armor3 => outcome.scores[Facet::Armor] += 3,
-      Armor4 => outcome.scores[Facet::Armor] += 4,
+      Armor1 => scores[Facet::Armor] += sign,
+      Armor2 => scores[Facet::Armor] += 2 * sign,
+      Armor3 => scores[Facet::Armor] += 3 * sign,
+      Armor4 => scores[Facet::Armor] += 4 * sign,
     }
   }
 
@@ -131,7 +155,7 @@ impl RollFace {
     static d20: Real = 1.0 / 20.0;
     match die {
       White => {
-        static ret: &'static [RollFace] = &[
+        static ret: &[RollFace] = &[
           RollFace { die: White, face: Action1, probability: 2.0 * d20 },
           RollFace { die: White, face: Crit1, probability: 3.0 * d20 },
           RollFace { die: White, face: Skill2, probability: 1.0 * d20 },
@@ -149,7 +173,7 @@ impl RollFace {
         ret
       },
       Red => {
-        static ret: &'static [RollFace] = &[
+        static ret: &[RollFace] = &[
           RollFace { die: Red, face: Armor1, probability: 4.0 * d12 },
           RollFace { die: Red, face: Armor2, probability: 3.0 * d12 },
           RollFace { die: Red, face: Armor3, probability: 3.0 * d12 },
@@ -158,7 +182,7 @@ impl RollFace {
         ret
       },
       Black => {
-        static ret: &'static [RollFace] = &[
+        static ret: &[RollFace] = &[
           RollFace { die: Black, face: Damage1, probability: 5.0 * d12 },
           RollFace { die: Black, face: Damage2, probability: 1.0 * d12 },
           RollFace { die: Black, face: Blank, probability: 3.0 * d12 },
@@ -169,7 +193,7 @@ impl RollFace {
         ret
       },
       Green => {
-        static ret: &'static [RollFace] = &[
+        static ret: &[RollFace] = &[
           RollFace { die: Green, face: Blank, probability: 4.0 * d12 },
           RollFace { die: Green, face: SkillMinus2, probability: 2.0 * d12 },
           RollFace { die: Green, face: SkillMinus3, probability: 1.0 * d12 },
@@ -180,7 +204,7 @@ impl RollFace {
         ret
       },
       Yellow => {
-        static ret: &'static [RollFace] = &[
+        static ret: &[RollFace] = &[
           RollFace { die: Yellow, face: Shred1, probability: 5.0 * d12 },
           RollFace { die: Yellow, face: Shred2, probability: 1.0 * d12 },
           RollFace { die: Yellow, face: Blank, probability: 2.0 * d12 },
@@ -191,7 +215,7 @@ impl RollFace {
         ret
       },
       Blue => {
-        static ret: &'static [RollFace] = &[
+        static ret: &[RollFace] = &[
           RollFace { die: Blue, face: Bottle1, probability: 4.0 * d12 },
           RollFace { die: Blue, face: Bottle2, probability: 2.0 * d12 },
           RollFace { die: Blue, face: BottleStar, probability: 1.0 * d12 },
@@ -204,3 +228,28 @@ impl RollFace {
     }
   }
 }
+
+/// Number of combinations of faces which dice at given positions of the roll could show once rerolled.
+pub fn reroll_count(roll: &[RollFace], indices: &[usize]) -> usize {
+  indices.iter().map(|&idx| RollFace::faces_of(roll[idx].die).len()).product()
+}
+
+/// Faces which rerolled dice show in the numbered combination, along with positions of the dice.
+/// Dice keep their types when rerolled, so the roll could be the one before or after the reroll.
+pub fn rerolled_faces<'r>(
+  roll: &'r [RollFace],
+  indices: &'r [usize],
+  mut combination: usize,
+) -> impl Iterator<Item = (usize, RollFace)> + 'r {
+  indices.iter().map(move |&idx| {
+    let faces = RollFace::faces_of(roll[idx].die);
+    let face = faces[combination % faces.len()];
+    combination /= faces.len();
+    (idx, face)
+  })
+}
+
+/// Whether skill rolled is low enough and no miss is rolled, as in attacks and other skill tests.
+pub fn passes(scores: &Scores, required_skill: Score) -> bool {
+  scores[Facet::Miss] == 0 && scores[Facet::Skill] <= required_skill
+}
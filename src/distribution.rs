@@ -0,0 +1,110 @@
+use common::*;
+
+/// Probabilities of non-negative whole values, such as damage dealt or number of attacks.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Distribution {
+  probabilities: Vec<Real>,
+}
+
+impl Distribution {
+  pub fn certain(value: usize) -> Distribution {
+    let mut distribution = Distribution::default();
+    distribution.add(value, 1.0);
+    distribution
+  }
+
+  /// Adds probability of the value. Values which are never reached are left out,
+  /// so that equal distributions compare equal however they were built.
+  pub fn add(&mut self, value: usize, probability: Real) {
+    if probability == 0.0 {
+      return;
+    }
+    if self.probabilities.len() <= value {
+      self.probabilities.resize(value + 1, 0.0);
+    }
+    self.probabilities[value] += probability;
+  }
+
+  /// Adds all values of the other distribution, as if it happens with given probability.
+  pub fn add_scaled(&mut self, other: &Distribution, probability: Real) {
+    for (value, part) in other.iter() {
+      self.add(value, part * probability);
+    }
+  }
+
+  pub fn probability(&self, value: usize) -> Real {
+    self.probabilities.get(value).cloned().unwrap_or(0.0)
+  }
+
+  pub fn at_least(&self, value: usize) -> Real {
+    self.probabilities.iter().skip(value).sum()
+  }
+
+  /// Sum of all probabilities. It is 1 unless the distribution is still being built.
+  pub fn total(&self) -> Real {
+    self.probabilities.iter().sum()
+  }
+
+  pub fn mean(&self) -> Real {
+    self.iter().map(|(value, probability)| value as Real * probability).sum()
+  }
+
+  /// The highest value with non-zero probability.
+  pub fn max(&self) -> usize {
+    self.probabilities.iter().rposition(|&probability| probability > 0.0).unwrap_or(0)
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = (usize, Real)> + '_ {
+    self.probabilities.iter()
+      .cloned()
+      .enumerate()
+      .filter(|&(_, probability)| probability > 0.0)
+  }
+
+  /// Distribution of the value increased by `by`.
+  pub fn shifted(&self, by: usize) -> Distribution {
+    let mut probabilities = vec![0.0; by];
+    probabilities.extend_from_slice(&self.probabilities);
+    Distribution { probabilities }
+  }
+}
+
+/// Converts a result of a single outcome, which is always whole, into a value of a distribution.
+pub fn whole(result: Real) -> usize {
+  result.round().max(0.0) as usize
+}
+
+#[cfg(test)]
+mod test {
+  use distribution::*;
+
+  fn coin() -> Distribution {
+    let mut coin = Distribution::default();
+    coin.add(0, 0.5);
+    coin.add(1, 0.5);
+    coin
+  }
+
+  #[test]
+  fn coin_statistics() {
+    let coin = coin();
+    assert_eq!(coin.probability(1), 0.5);
+    assert_eq!(coin.probability(2), 0.0);
+    assert_eq!(coin.at_least(1), 0.5);
+    assert_eq!(coin.mean(), 0.5);
+    assert_eq!(coin.max(), 1);
+  }
+
+  #[test]
+  fn shift_and_mix() {
+    let mut mixed = Distribution::default();
+    mixed.add_scaled(&coin().shifted(2), 0.5);
+    mixed.add_scaled(&Distribution::certain(0), 0.5);
+    assert_eq!(mixed.total(), 1.0);
+    mixed.add(10, 0.0);
+    assert_eq!(mixed.max(), 3);
+    assert_eq!(mixed.probability(0), 0.5);
+    assert_eq!(mixed.probability(1), 0.0);
+    assert_eq!(mixed.probability(3), 0.25);
+  }
+}
@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+use common::*;
+use attack;
+use distribution::Distribution;
+use sequence;
+
+pub struct Unit<'d, 'a> {
+  /// Attack of the unit. It is made against the other unit, whatever target it has.
+  pub attack: &'d attack::Disposition<'a>,
+  /// The unit itself, as a target of the other one. Unit without hit points goes down
+  /// only when killed outright.
+  pub target: attack::Target,
+}
+
+/// Two units attacking each other in turns until one of them is down.
+/// In every round the first unit attacks, and then the second one does if it is still up.
+pub struct Duel<'d, 'a> {
+  pub first: Unit<'d, 'a>,
+  pub second: Unit<'d, 'a>,
+  /// Duel is left unresolved after that many rounds.
+  pub max_rounds: usize,
+}
+
+#[derive(Debug)]
+pub struct DuelResults {
+  pub first_wins: Real,
+  pub second_wins: Real,
+  /// Distribution of the round in which one of units goes down.
+  pub rounds: Distribution,
+  /// Unresolved duels count as lasting the maximum number of rounds.
+  pub expected_rounds: Real,
+}
+
+impl<'d, 'a> Duel<'d, 'a> {
+  pub fn results(&self) -> DuelResults {
+    let mut first_attack = sequence::Outcomes::new(self.first.attack);
+    let mut second_attack = sequence::Outcomes::new(self.second.attack);
+    let mut results = DuelResults {
+      first_wins: 0.0,
+      second_wins: 0.0,
+      rounds: Distribution::default(),
+      expected_rounds: 0.0,
+    };
+    // Probabilities of both units being as they are, while both are up.
+    let mut states = HashMap::new();
+    states.insert((self.first.target.clone(), self.second.target.clone()), 1.0);
+    for round in 1..=self.max_rounds {
+      let mut next = HashMap::new();
+      for ((first, second), probability) in states {
+        for (outcome, &first_probability) in first_attack.against(&second) {
+          let probability = probability * first_probability;
+          if outcome.down {
+            results.first_wins += probability;
+            results.rounds.add(round, probability);
+            continue;
+          }
+          let second = outcome.after(&second);
+          for (outcome_back, &second_probability) in second_attack.against(&first) {
+            let probability = probability * second_probability;
+            if outcome_back.down {
+              results.second_wins += probability;
+              results.rounds.add(round, probability);
+            } else {
+              *next.entry((outcome_back.after(&first), second.clone())).or_insert(0.0) += probability;
+            }
+          }
+        }
+      }
+      states = next;
+    }
+    let unresolved: Real = states.values().sum();
+    results.expected_rounds = results.rounds.mean() + unresolved * self.max_rounds as Real;
+    results
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use duel::*;
+  use dice;
+
+  fn shooter(required_skill: Score) -> attack::Disposition<'static> {
+    let mut shooter = attack::Disposition::test_shot(vec![dice::White], vec![]);
+    shooter.characteristics.required_skill = required_skill;
+    shooter
+  }
+
+  #[test]
+  fn first_shot_decides_one_hp_duel() {
+    let good = shooter(12);
+    let bad = shooter(6);
+    let hit = |disposition: &attack::Disposition| disposition.distribution(attack::Hits).probability(1);
+    let (good_hit, bad_hit) = (hit(&good), hit(&bad));
+    let results = Duel {
+      first: Unit { attack: &good, target: attack::Target::test_hp(1) },
+      second: Unit { attack: &bad, target: attack::Target::test_hp(1) },
+      max_rounds: 200,
+    }.results();
+    // Chance of a round without anybody going down.
+    let stall = (1.0 - good_hit) * (1.0 - bad_hit);
+    assert!((results.first_wins - good_hit / (1.0 - stall)).abs() < 1e-9);
+    assert!((results.first_wins + results.second_wins - 1.0).abs() < 1e-9);
+    assert!((results.rounds.probability(1) - (1.0 - stall)).abs() < 1e-9);
+    assert!((results.expected_rounds - 1.0 / (1.0 - stall)).abs() < 1e-9);
+  }
+
+  #[test]
+  fn unresolved_duel_lasts_all_rounds() {
+    // Skill without dice is 0, which is too high.
+    let blind = attack::Disposition { dice: vec![], ..shooter(-1) };
+    let results = Duel {
+      first: Unit { attack: &blind, target: attack::Target::test_hp(2) },
+      second: Unit { attack: &blind, target: attack::Target::test_hp(2) },
+      max_rounds: 5,
+    }.results();
+    assert_eq!((results.first_wins, results.second_wins), (0.0, 0.0));
+    assert_eq!(results.rounds.total(), 0.0);
+    assert!((results.expected_rounds - 5.0).abs() < 1e-9);
+  }
+
+  #[test]
+  fn radiation_builds_up_over_rounds() {
+    let mut ray = attack::Disposition::test_shot(vec![], vec![]);
+    ray.characteristics.base_score = dice::new_scores(&[(dice::Hit, 1), (dice::Rad, 2)]);
+    let blind = attack::Disposition { dice: vec![], ..shooter(-1) };
+    let irradiated = attack::Target { rad_threshold: PositiveScore::new(3), ..attack::Target::test_hp(1) };
+    let results = Duel {
+      first: Unit { attack: &ray, target: attack::Target::test_hp(1) },
+      second: Unit { attack: &blind, target: irradiated },
+      max_rounds: 5,
+    }.results();
+    // The second hit takes radiation up to the threshold.
+    assert_eq!(results.first_wins, 1.0);
+    assert_eq!(results.rounds, Distribution::certain(2));
+  }
+}
@@ -1,6 +1,8 @@
 use attack;
+use combinators;
 use dice;
 use common::*;
+use std::convert::TryInto;
 
 pub struct ExchangeScoreOnHit {
   pub give: (dice::Facet, PositiveScore),
@@ -41,11 +43,8 @@ impl attack::Effect for IgnoreArmorOnBottles {
     case: &'a mut attack::Case<'b>,
   ) -> attack::LastAlteration {
     if case.result(attack::Hits) > 0.0 && case.score(dice::Bottle) > 0 {
-      let scored_armor = case.score(dice::Armor);
-      let shredded_armor = case.characteristics().soft_armor - case.score(dice::Shred);
-      let activated_armor = if scored_armor > shredded_armor { 0 } else { scored_armor };
-      let scored_damage = case.score(dice::Damage);
-      let available_armor = activated_armor + case.characteristics().hard_armor;
+      let scored_damage = case.raw_damage();
+      let available_armor = case.armor();
       let potential_effect_damage = available_armor.min(scored_damage).min(case.score(dice::Bottle));
       let current_result_damage = scored_damage - available_armor;
       if potential_effect_damage > current_result_damage {
@@ -60,6 +59,28 @@ impl attack::Effect for IgnoreArmorOnBottles {
   }
 }
 
+/// Ignores some soft and hard armour of the target. Armour only matters on hit anyway,
+/// so it is pierced whether the case hits yet or not.
+pub struct PierceArmor {
+  pub soft: Score,
+  pub hard: Score,
+}
+
+impl attack::Effect for PierceArmor {
+  fn yield_alterations(
+    &self,
+    applicator: &mut attack::EffectApplicator,
+    case: &mut attack::Case,
+  ) -> attack::LastAlteration {
+    case.update(|updater| {
+      let characteristics = updater.characteristics_mut();
+      characteristics.ignored_soft_armor += self.soft;
+      characteristics.ignored_hard_armor += self.hard;
+    });
+    applicator.yield_last_alteration(case, 1.0)
+  }
+}
+
 pub struct RerollBlackBlank {}
 
 impl attack::Effect for RerollBlackBlank {
@@ -68,15 +89,12 @@ impl attack::Effect for RerollBlackBlank {
     applicator: &mut attack::EffectApplicator,
     case: &mut attack::Case,
   ) -> attack::LastAlteration {
-    let blank_idx = case.roll().iter()
-      .enumerate()
-      .find(|(_, roll)| roll.die == dice::Black && roll.face == dice::Blank)
-      .map(|needle| needle.0);
-    if let Some(idx) = blank_idx {
-      reroll(applicator, case, dice::Black, idx)
-    } else {
-      applicator.yield_last_alteration(case, 1.0)
-    }
+    let reroll = Reroll {
+      up_to: 1.try_into().unwrap(),
+      filter: DieFilter { dice: &[dice::Black], faces: &[dice::Blank] },
+      policy: RerollPolicy::First,
+    };
+    reroll.yield_alterations(applicator, case)
   }
 }
 
@@ -92,15 +110,15 @@ impl attack::Effect for RerollAnyBlank {
       .cloned()
       .enumerate()
       .find(|(_, roll)| roll.die != dice::Green && roll.face == dice::Blank);
-    if let Some((idx, roll)) = blank_idx {
-      reroll(applicator, case, roll.die, idx)
+    if let Some((idx, _)) = blank_idx {
+      applicator.reroll(case, &[idx])
     } else if case.result(attack::Hits) > 0.0 && !near_hit(case, 2) {
       let blank_idx = case.roll().iter()
         .enumerate()
         .find(|(_, roll)| roll.die == dice::Green && roll.face == dice::Blank)
         .map(|needle| needle.0);
       if let Some(idx) = blank_idx {
-        reroll(applicator, case, dice::Green, idx)
+        applicator.reroll(case, &[idx])
       } else {
         applicator.yield_last_alteration(case, 1.0)
       }
@@ -110,7 +128,7 @@ impl attack::Effect for RerollAnyBlank {
         .find(|(_, roll)| roll.die == dice::Green && roll.face == dice::Blank)
         .map(|needle| needle.0);
       if let Some(idx) = blank_idx {
-        reroll(applicator, case, dice::Green, idx)
+        applicator.reroll(case, &[idx])
       } else {
         applicator.yield_last_alteration(case, 1.0)
       }
@@ -120,6 +138,175 @@ impl attack::Effect for RerollAnyBlank {
   }
 }
 
+/// Selects dice of the roll by their type and the face they show. Empty lists match anything.
+/// Lists are borrowed, so that filters of built-in effects are made without allocations.
+#[derive(Clone, Copy, Default)]
+pub struct DieFilter<'f> {
+  pub dice: &'f [dice::Type],
+  pub faces: &'f [dice::Face],
+}
+
+impl<'f> DieFilter<'f> {
+  pub fn matches(&self, face: &dice::RollFace) -> bool {
+    (self.dice.is_empty() || self.dice.contains(&face.die)) && (self.faces.is_empty() || self.faces.contains(&face.face))
+  }
+}
+
+/// How `Reroll` chooses which of matching dice to reroll.
+#[derive(Clone, Copy, PartialEq)]
+pub enum RerollPolicy {
+  /// Rerolls first matching dice, as many as allowed.
+  First,
+  /// Rerolls dice which give the best expected results right after the reroll, possibly none of them.
+  /// Results are compared the same way as in `combinators::BestOf`.
+  Best,
+  /// Rerolls dice which give the worst expected results, as the defender would.
+  Worst,
+}
+
+/// Rerolls up to given number of dice matching the filter.
+pub struct Reroll<'f> {
+  pub up_to: PositiveScore,
+  pub filter: DieFilter<'f>,
+  pub policy: RerollPolicy,
+}
+
+impl<'f> attack::Effect for Reroll<'f> {
+  fn yield_alterations(
+    &self,
+    applicator: &mut attack::EffectApplicator,
+    case: &mut attack::Case,
+  ) -> attack::LastAlteration {
+    let mut matching = case.roll().iter()
+      .enumerate()
+      .filter(|(_, face)| self.filter.matches(face))
+      .map(|(idx, _)| idx)
+      .collect::<Vec<_>>();
+    let up_to = (self.up_to.get() as usize).min(matching.len());
+    let chosen = match self.policy {
+      RerollPolicy::First => {
+        matching.truncate(up_to);
+        matching
+      },
+      RerollPolicy::Best => choose_reroll(case, &matching, up_to, combinators::is_better),
+      RerollPolicy::Worst => choose_reroll(case, &matching, up_to, |lhs, rhs| combinators::is_better(rhs, lhs)),
+    };
+    applicator.reroll(case, &chosen)
+  }
+}
+
+/// Picks a subset of candidate dice, no bigger than `up_to`, whose reroll gives the preferred expected results.
+/// Not rerolling anything is a candidate too.
+fn choose_reroll(
+  case: &attack::Case,
+  candidates: &[usize],
+  up_to: usize,
+  prefer: impl Fn(&attack::Scores, &attack::Scores) -> bool,
+) -> Vec<usize> {
+  let mut best = (vec![], *case.results());
+  for mask in 1..(1usize << candidates.len()) {
+    if mask.count_ones() as usize > up_to {
+      continue;
+    }
+    let indices = candidates.iter()
+      .enumerate()
+      .filter(|(bit, _)| mask & (1 << bit) != 0)
+      .map(|(_, &idx)| idx)
+      .collect::<Vec<_>>();
+    let mut roll = case.roll().to_vec();
+    let mut expected = attack::Scores::default();
+    for combination in 0..dice::reroll_count(case.roll(), &indices) {
+      let mut probability = 1.0;
+      for (idx, face) in dice::rerolled_faces(case.roll(), &indices, combination) {
+        roll[idx] = face;
+        probability *= face.probability;
+      }
+      let (_, results) = attack::compute_output(case.characteristics(), &roll);
+      attack::update(&mut expected, &results, |sum, part| *sum += *part * probability);
+    }
+    if prefer(&expected, &best.1) {
+      best = (indices, expected);
+    }
+  }
+  best.0
+}
+
+/// Defender rerolls the Red armour die, if it is expected to lower damage.
+pub struct RerollArmor {}
+
+impl attack::Effect for RerollArmor {
+  fn yield_alterations(
+    &self,
+    applicator: &mut attack::EffectApplicator,
+    case: &mut attack::Case,
+  ) -> attack::LastAlteration {
+    let reroll = Reroll {
+      up_to: 1.try_into().unwrap(),
+      filter: DieFilter { dice: &[dice::Red], ..Default::default() },
+      policy: RerollPolicy::Worst,
+    };
+    reroll.yield_alterations(applicator, case)
+  }
+}
+
+/// Defender rolls one more Red armour die. The die is rolled along with the attack,
+/// so the defender can't see the attack roll first and has to take the die in any case.
+/// Extra armour could also do harm, as armour above soft armour of the target doesn't count.
+pub struct ExtraArmorDie {}
+
+impl attack::Effect for ExtraArmorDie {
+  fn yield_alterations(
+    &self,
+    applicator: &mut attack::EffectApplicator,
+    case: &mut attack::Case,
+  ) -> attack::LastAlteration {
+    let branches = dice::RollFace::faces_of(dice::Red).iter()
+      .map(|&face| (face.probability, move |updater: &mut attack::CaseUpdater| updater.add_face(face)))
+      .collect::<Vec<_>>();
+    applicator.branch(case, &branches)
+  }
+}
+
+/// When `RerollWhite` rerolls the White die.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WhiteRerollCondition {
+  /// White die shows one of miss faces.
+  OnMissFace,
+  /// White die shows a miss face or skill is too high to hit.
+  OnMiss,
+  Always,
+}
+
+/// Rerolls the White die.
+pub struct RerollWhite {
+  pub condition: WhiteRerollCondition,
+}
+
+impl attack::Effect for RerollWhite {
+  fn yield_alterations(
+    &self,
+    applicator: &mut attack::EffectApplicator,
+    case: &mut attack::Case,
+  ) -> attack::LastAlteration {
+    let white = case.roll().iter()
+      .cloned()
+      .enumerate()
+      .find(|(_, roll)| roll.die == dice::White);
+    let shows_miss = |roll: &dice::RollFace| roll.face == dice::Miss1 || roll.face == dice::MissAction;
+    let applies = |roll: &dice::RollFace| match self.condition {
+      WhiteRerollCondition::OnMissFace => shows_miss(roll),
+      WhiteRerollCondition::OnMiss => {
+        shows_miss(roll) || (case.result(attack::Hits) == 0.0 && case.score(dice::Skill) > case.characteristics().required_skill)
+      },
+      WhiteRerollCondition::Always => true,
+    };
+    match white {
+      Some((idx, roll)) if applies(&roll) => applicator.reroll(case, &[idx]),
+      _ => applicator.yield_last_alteration(case, 1.0),
+    }
+  }
+}
+
 // Luck effects should be added in an order as presented in this file. Otherwise it is possible to break the rules.
 // E.g. use luck to generate a hit after luck was used to generate a miss.
 pub struct LuckForHit {}
@@ -194,16 +381,6 @@ impl attack::Effect for LuckForCrit {
   }
 }
 
-fn reroll(applicator: &mut attack::EffectApplicator, case: &mut attack::Case, die: dice::Type, die_idx: usize) -> attack::LastAlteration {
-  let faces = dice::RollFace::faces_of(die);
-  for &face in faces.iter().skip(1) {
-    case.update(|updater| { updater.roll_mut()[die_idx] = face; });
-    applicator.yield_next_alteration(case, face.probability);
-  }
-  case.update(|updater| { updater.roll_mut()[die_idx] = faces[0]; });
-  applicator.yield_last_alteration(case, faces[0].probability)
-}
-
 fn near_hit(case: &attack::Case, amount: Score) -> bool {
   case.result(attack::Hits) > 0.0 && case.score(dice::Hit) == 0 && case.score(dice::Skill) + amount > case.characteristics().required_skill
 }
@@ -225,7 +402,10 @@ mod test {
       dice: vec![dice::Red, dice::Blue],
       characteristics: attack::Characteristics {
         base_score: dice::new_scores(&[(dice::Hit, 1), (dice::Damage, 1)]),
-        soft_armor: 1,
+        target: attack::Target {
+          physical_armor: attack::Armor { soft_armor: 1, hard_armor: 0 },
+          ..Default::default()
+        },
         ..Default::default()
       },
       effects: vec![effect],
@@ -236,6 +416,31 @@ mod test {
     assert_eq!((outcome[attack::Damage] * 144.0).round() as i64, 124);
   }
 
+  #[test]
+  fn ignore_armor_on_bottles_after_crit() {
+    let effect = &IgnoreArmorOnBottles{};
+    let damage = |crit_effect, scores: &[(dice::Facet, Score)], effects| attack::Disposition {
+      characteristics: attack::Characteristics {
+        base_score: dice::new_scores(scores),
+        crit_effect,
+        target: attack::Target {
+          physical_armor: attack::Armor { soft_armor: 3, hard_armor: 0 },
+          ..Default::default()
+        },
+        ..Default::default()
+      },
+      effects,
+      ..Default::default()
+    }.average_scores()[attack::Damage];
+    let scores = &[(dice::Hit, 1), (dice::Crit, 1), (dice::Damage, 3), (dice::Bottle, 2), (dice::Armor, 2)];
+    assert_eq!(damage(attack::CritEffect::IgnoreArmor, scores, vec![]), 3.0);
+    assert_eq!(damage(attack::CritEffect::IgnoreArmor, scores, vec![effect]), 3.0);
+    // Crit adds 2 damage, which is all stopped by armour without the effect.
+    let scores = &[(dice::Hit, 1), (dice::Crit, 1), (dice::Damage, 1), (dice::Bottle, 2), (dice::Armor, 3)];
+    assert_eq!(damage(attack::CritEffect::ExtraDamage(2), scores, vec![]), 0.0);
+    assert_eq!(damage(attack::CritEffect::ExtraDamage(2), scores, vec![effect]), 2.0);
+  }
+
   #[test]
   fn deal_one_damage_on_bottle() {
     let effect = &ExchangeScoreOnHit{
@@ -274,6 +479,112 @@ mod test {
     assert_eq!((outcome[attack::Damage] * 144.0).round() as i64, 7 * 3 + 7 * 12);
   }
 
+  #[test]
+  fn reroll_two_blacks_statistics() {
+    let effect = &Reroll {
+      up_to: 2.try_into().unwrap(),
+      filter: DieFilter { dice: &[dice::Black], faces: &[dice::Blank] },
+      policy: RerollPolicy::First,
+    };
+    let disposition = attack::Disposition {
+      dice: vec![dice::Black, dice::Black],
+      characteristics: attack::Characteristics {
+        base_score: dice::new_scores(&[(dice::Hit, 1)]),
+        ..Default::default()
+      },
+      effects: vec![effect],
+      ..Default::default()
+    };
+    let outcome = disposition.average_scores();
+
+    assert_eq!((outcome[attack::Damage] * 144.0).round() as i64, 2 * (7 * 3 + 7 * 12));
+  }
+
+  #[test]
+  fn best_reroll_keeps_hits() {
+    let white = DieFilter { dice: &[dice::White], ..Default::default() };
+    let first = &Reroll { up_to: 1.try_into().unwrap(), filter: white, policy: RerollPolicy::First };
+    let best = &Reroll { up_to: 1.try_into().unwrap(), filter: white, policy: RerollPolicy::Best };
+    let plain = attack::Disposition::test_shot(vec![dice::White], vec![]).average_scores();
+    let first = attack::Disposition::test_shot(vec![dice::White], vec![first]).average_scores();
+    let best = attack::Disposition::test_shot(vec![dice::White], vec![best]).average_scores();
+
+    assert_eq!((first[attack::Hits] * 1e9).round(), (plain[attack::Hits] * 1e9).round());
+    assert_eq!((best[attack::Hits] * 400.0).round() as i64, 11 * 20 + 9 * 11);
+  }
+
+  /// Always hits for 3 damage against soft armour 2.
+  fn armored_target<'a>(dice: Vec<dice::Type>, effects: Vec<&'a dyn attack::Effect>) -> attack::Disposition<'a> {
+    let mut disposition = attack::Disposition::test_shot(dice, effects);
+    disposition.characteristics.base_score = dice::new_scores(&[(dice::Hit, 1), (dice::Damage, 3)]);
+    disposition.characteristics.target.physical_armor.soft_armor = 2;
+    disposition
+  }
+
+  #[test]
+  fn reroll_armor_statistics() {
+    let effect = &RerollArmor{};
+    let outcome = armored_target(vec![dice::Red], vec![effect]).average_scores();
+
+    assert_eq!((outcome[attack::Damage] * 144.0).round() as i64, 4 * 2 * 12 + 3 * 12 + 5 * 26);
+  }
+
+  #[test]
+  fn extra_armor_die_statistics() {
+    let effect = &ExtraArmorDie{};
+    // Armour 1 and 2 lower damage, armour above 2 doesn't count.
+    let outcome = armored_target(vec![], vec![effect]).average_scores();
+    assert_eq!((outcome[attack::Damage] * 12.0).round() as i64, 4 * 2 + 3 + 5 * 3);
+
+    // Extra die counts as if it was rolled in the first place, even when it does harm.
+    let with_die = armored_target(vec![dice::Red], vec![effect]).average_scores();
+    let two_dice = armored_target(vec![dice::Red, dice::Red], vec![]).average_scores();
+    let one_die = armored_target(vec![dice::Red], vec![]).average_scores();
+    assert_eq!((with_die[attack::Damage] * 1e9).round(), (two_dice[attack::Damage] * 1e9).round());
+    assert!(with_die[attack::Damage] > one_die[attack::Damage]);
+  }
+
+  #[test]
+  fn pierce_armor_statistics() {
+    let effect = &PierceArmor { soft: 1, hard: 0 };
+    let outcome = armored_target(vec![dice::Red], vec![effect]).average_scores();
+
+    assert_eq!((outcome[attack::Damage] * 12.0).round() as i64, 4 * 3 + 3 * 2 + 5 * 3);
+  }
+
+  #[test]
+  fn pierce_armor_before_reroll_applies_to_later_hits() {
+    let pierce = &PierceArmor { soft: 1, hard: 0 };
+    let reroll = &RerollWhite { condition: WhiteRerollCondition::OnMiss };
+    let damage = |effects: Vec<&dyn attack::Effect>| {
+      let mut disposition = armored_target(vec![dice::White, dice::Red], effects);
+      disposition.characteristics.base_score = dice::new_scores(&[(dice::Damage, 3)]);
+      disposition.average_scores()[attack::Damage]
+    };
+
+    assert_eq!((damage(vec![pierce, reroll]) * 1e9).round(), (damage(vec![reroll, pierce]) * 1e9).round());
+  }
+
+  #[test]
+  fn reroll_white_statistics() {
+    fn hits(condition: WhiteRerollCondition) -> Real {
+      let effect = &RerollWhite { condition };
+      attack::Disposition {
+        dice: vec![dice::White],
+        characteristics: attack::Characteristics {
+          required_skill: 6,
+          ..Default::default()
+        },
+        effects: vec![effect],
+        ..Default::default()
+      }.average_scores()[attack::Hits]
+    }
+
+    assert_eq!((hits(WhiteRerollCondition::Always) * 20.0).round() as i64, 11);
+    assert_eq!((hits(WhiteRerollCondition::OnMissFace) * 400.0).round() as i64, 11 * 20 + 3 * 11);
+    assert_eq!((hits(WhiteRerollCondition::OnMiss) * 400.0).round() as i64, 11 * 20 + 9 * 11);
+  }
+
   #[test]
   fn reroll_green_as_any_statistics() {
     let effect = &RerollAnyBlank{};
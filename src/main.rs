@@ -4,12 +4,20 @@ extern crate enum_map;
 
 use std::convert::TryInto;
 
+mod analysis;
 mod attack;
 mod cartesian_fold;
+mod chain;
+mod combinators;
 mod dice;
+mod distribution;
+mod duel;
 mod effects;
 mod outcome;
+mod predicate;
 mod rollup;
+mod sequence;
+mod skill;
 
 mod common {
   pub type Real = f64;
@@ -48,6 +56,18 @@ fn main() {
   let armor_luck = &effects::LuckForArmor{};
   let crit_luck = &effects::LuckForCrit{};
 
+  let damage_or_shred_on_bottle = &combinators::BestOf(vec![damage_on_bottle, shred_on_bottle]);
+  let reroll_black_blanks = &combinators::Times(2.try_into().unwrap(), rbb);
+  let all_luck = &combinators::Sequence(vec![hit_luck, miss_luck, armor_luck, crit_luck]);
+  let luck_on_bottle = &combinators::When(|case: &attack::Case| case.score(dice::Bottle) > 0, all_luck);
+  let reroll_two_blanks = &effects::Reroll {
+    up_to: 2.try_into().unwrap(),
+    filter: effects::DieFilter { faces: &[dice::Blank], ..Default::default() },
+    policy: effects::RerollPolicy::Best,
+  };
+  let near_miss: predicate::Condition = "miss and skill within 1 of required".parse().unwrap();
+  let reroll_near_miss = &combinators::When(near_miss, rab);
+
   println!(
     "cowboy: {:?}",
     attack::Disposition {
@@ -62,8 +82,11 @@ fn main() {
           (dice::Damage, 1),
         ]),
         required_skill: 6,
-        soft_armor: 2,
-        hard_armor: 0,
+        target: attack::Target {
+          physical_armor: attack::Armor { soft_armor: 2, hard_armor: 0 },
+          ..Default::default()
+        },
+        ..Default::default()
       },
       effects: vec![],
       ..Default::default()
@@ -72,50 +95,299 @@ fn main() {
   );
 
   println!(
-    "sniper: {:?}",
-    attack::Disposition {
-      dice: vec![dice::Red, dice::White, dice::Green, dice::Green, dice::Blue],
-      characteristics: attack::Characteristics {
-        base_score: dice::new_scores(&[
-          (dice::Damage, 2),
-        ]),
-        required_skill: 9,
-        soft_armor: 5,
-        hard_armor: 1,
-      },
-
-      effects: vec![shred_on_bottle, damage_on_star],
-      ..Default::default()
-    }
-    .average_scores()
-  );
-
-  println!(
-    "big guy: {:?}",
+    "lucky cowboy: {:?}",
     attack::Disposition {
       dice: vec![
         dice::Red,
         dice::White,
         dice::Black,
         dice::Black,
-        dice::Yellow,
-        dice::Green,
-        dice::Green
       ],
       characteristics: attack::Characteristics {
         base_score: dice::new_scores(&[
           (dice::Damage, 1),
         ]),
-        required_skill: 9,
-        soft_armor: 2,
-        hard_armor: 1,
+        required_skill: 6,
+        target: attack::Target {
+          physical_armor: attack::Armor { soft_armor: 2, hard_armor: 0 },
+          ..Default::default()
+        },
+        ..Default::default()
       },
-      effects: vec![rbb, rab, hit_luck, miss_luck, ignore_armor_on_bottles, armor_luck, crit_luck],
+      effects: vec![reroll_two_blanks],
       ..Default::default()
     }
     .average_scores()
   );
 
+  for &armor_rule in [attack::ArmorRule::Sum, attack::ArmorRule::Best].iter() {
+    let cowboy_in_cover = attack::Disposition {
+      dice: vec![dice::Red, dice::Red, dice::White, dice::Black, dice::Black],
+      characteristics: attack::Characteristics {
+        base_score: dice::new_scores(&[(dice::Damage, 1)]),
+        required_skill: 6,
+        target: attack::Target {
+          physical_armor: attack::Armor { soft_armor: 2, hard_armor: 0 },
+          ..Default::default()
+        },
+        armor_rule,
+        ..Default::default()
+      },
+      ..Default::default()
+    };
+    println!("cowboy against cover, {:?} armour: {:?}", armor_rule, cowboy_in_cover.average_scores());
+  }
+
+  let grenadier = attack::Disposition {
+    dice: vec![dice::Red, dice::White, dice::Blue, dice::Blue],
+    characteristics: attack::Characteristics {
+      base_score: dice::new_scores(&[
+        (dice::Damage, 2),
+      ]),
+      required_skill: 6,
+      target: attack::Target {
+        physical_armor: attack::Armor { soft_armor: 2, hard_armor: 0 },
+        ..Default::default()
+      },
+      explosion_damage: 2,
+      ..Default::default()
+    },
+    blast_targets: vec![
+      attack::Armor { soft_armor: 2, hard_armor: 0 },
+      attack::Armor { soft_armor: 4, hard_armor: 1 },
+    ],
+    ..Default::default()
+  };
+  println!("grenadier: {:?}", grenadier.average_scores());
+  println!("  blast damage per target: {:?}", grenadier.blast_damage());
+
+  let gunslinger = attack::Disposition {
+    dice: vec![dice::Red, dice::White, dice::Black, dice::Green],
+    characteristics: attack::Characteristics {
+      base_score: dice::new_scores(&[
+        (dice::Damage, 2),
+      ]),
+      required_skill: 7,
+      target: attack::Target {
+        physical_armor: attack::Armor { soft_armor: 1, hard_armor: 0 },
+        ..Default::default()
+      },
+      ..Default::default()
+    },
+    ..Default::default()
+  };
+  println!("gunslinger: {:?}", gunslinger.average_scores());
+  println!("  damage distribution: {:?}", gunslinger.distribution(attack::Damage));
+  let follow_ups = chain::ActionChain {
+    first: &gunslinger,
+    actions: chain::ActionUse::Attack(&gunslinger),
+    max_attacks: 4,
+  };
+  let follow_up_damage = follow_ups.damage();
+  println!(
+    "  with follow-up attacks: mean damage {}, no damage {}, at least 6 damage {}, up to {}, total probability {}",
+    follow_up_damage.mean(),
+    follow_up_damage.probability(0),
+    follow_up_damage.at_least(6),
+    follow_up_damage.max(),
+    follow_up_damage.total(),
+  );
+  let other_actions = chain::ActionChain { first: &gunslinger, actions: chain::ActionUse::Value(1), max_attacks: 1 };
+  println!("  with actions worth 1 damage: {:?}", other_actions.damage());
+  let two_guns = sequence::AttackSequence {
+    attacks: vec![&gunslinger, &gunslinger],
+    target: attack::Target { hp: Some(4.try_into().unwrap()), ..gunslinger.characteristics.target.clone() },
+  }.results();
+  println!("  with two guns against 4 HP: {:?}", two_guns);
+  let eight_hp = attack::Target { hp: Some(8.try_into().unwrap()), ..gunslinger.characteristics.target.clone() };
+  let attacks_to_kill = sequence::attacks_to_kill(&gunslinger, &eight_hp, 30);
+  println!("  attacks to bring down 8 HP: {:?}", attacks_to_kill);
+  println!("  turns to bring down 8 HP, 2 attacks a turn: {:?}", sequence::turns_to_kill(&attacks_to_kill, 2));
+  let duel = duel::Duel {
+    first: duel::Unit {
+      attack: &gunslinger,
+      target: attack::Target {
+        physical_armor: attack::Armor { soft_armor: 1, hard_armor: 0 },
+        hp: Some(5.try_into().unwrap()),
+        ..Default::default()
+      },
+    },
+    second: duel::Unit {
+      attack: &grenadier,
+      target: attack::Target {
+        physical_armor: attack::Armor { soft_armor: 2, hard_armor: 0 },
+        hp: Some(4.try_into().unwrap()),
+        ..Default::default()
+      },
+    },
+    max_rounds: 20,
+  };
+  println!("  duel against grenadier: {:?}", duel.results());
+
+  for &damage_type in [
+    attack::DamageType::Physical,
+    attack::DamageType::Energy,
+    attack::DamageType::Radiation,
+    attack::DamageType::Fire,
+  ].iter() {
+    let against_power_armor = attack::Disposition {
+      dice: vec![dice::Red, dice::White, dice::Black, dice::Black],
+      characteristics: attack::Characteristics {
+        base_score: dice::new_scores(&[
+          (dice::Damage, 2),
+        ]),
+        required_skill: 6,
+        damage_type,
+        target: attack::Target {
+          physical_armor: attack::Armor { soft_armor: 3, hard_armor: 2 },
+          energy_armor: attack::Armor { soft_armor: 2, hard_armor: 1 },
+          radiation_armor: attack::Armor { soft_armor: 4, hard_armor: 0 },
+          fire_armor: attack::Armor { soft_armor: 2, hard_armor: 0 },
+          ..Default::default()
+        },
+        ..Default::default()
+      },
+      ..Default::default()
+    };
+    println!("{:?} weapon against power armour: {:?}", damage_type, against_power_armor.average_scores());
+  }
+
+  let rad_on_star = &effects::ExchangeScoreOnHit {
+    give: (dice::Star, 1.try_into().unwrap()),
+    take: (dice::Rad, 2.try_into().unwrap()),
+    times: None,
+  };
+  let gamma_gun = attack::Disposition {
+    dice: vec![dice::Red, dice::White, dice::Blue, dice::Blue],
+    characteristics: attack::Characteristics {
+      base_score: dice::new_scores(&[
+        (dice::Damage, 1),
+        (dice::Rad, 2),
+      ]),
+      required_skill: 7,
+      damage_type: attack::DamageType::Radiation,
+      target: attack::Target {
+        rad_resistance: 1,
+        rad: 2,
+        rad_threshold: Some(3.try_into().unwrap()),
+        ..Default::default()
+      },
+      ..Default::default()
+    },
+    effects: vec![rad_on_star],
+    ..Default::default()
+  };
+  println!("gamma gun: {:?}", gamma_gun.average_scores());
+
+  let sniper = attack::Disposition {
+    dice: vec![dice::Red, dice::White, dice::Green, dice::Green, dice::Blue],
+    characteristics: attack::Characteristics {
+      base_score: dice::new_scores(&[
+        (dice::Damage, 2),
+      ]),
+      required_skill: 9,
+      target: attack::Target {
+        physical_armor: attack::Armor { soft_armor: 5, hard_armor: 1 },
+        ..Default::default()
+      },
+      ..Default::default()
+    },
+
+    effects: vec![shred_on_bottle, damage_on_star],
+    ..Default::default()
+  };
+
+  println!("sniper: {:?}", sniper.average_scores());
+  for marginal in analysis::effect_subsets(&sniper) {
+    println!("  with effects {:?}: {:?}", marginal.effects, marginal.delta);
+  }
+  for &condition in [
+    effects::WhiteRerollCondition::OnMissFace,
+    effects::WhiteRerollCondition::OnMiss,
+    effects::WhiteRerollCondition::Always,
+  ].iter() {
+    let steady_hands = &effects::RerollWhite { condition };
+    let steady_sniper = attack::Disposition {
+      effects: vec![steady_hands, shred_on_bottle, damage_on_star],
+      ..sniper.clone()
+    };
+    println!("  with white reroll {:?}: {:?}", condition, steady_sniper.average_scores());
+  }
+  let armor_piercing = &effects::PierceArmor { soft: 1, hard: 1 };
+  let piercing_sniper = attack::Disposition {
+    effects: vec![shred_on_bottle, damage_on_star, armor_piercing],
+    ..sniper.clone()
+  };
+  println!("  with armour piercing: {:?}", piercing_sniper.average_scores());
+  let mut sniper_against_wounded = sniper.clone();
+  sniper_against_wounded.characteristics.target.hp = Some(2.try_into().unwrap());
+  println!("  against target with 2 HP: {:?}", sniper_against_wounded.average_scores());
+  for &crit_effect in [
+    attack::CritEffect::Maim,
+    attack::CritEffect::ExtraDamage(2),
+    attack::CritEffect::IgnoreArmor,
+    attack::CritEffect::KillWounded,
+  ].iter() {
+    let mut critical_sniper = sniper.clone();
+    critical_sniper.characteristics.crit_effect = crit_effect;
+    critical_sniper.characteristics.target.wounded = true;
+    println!("  with crit effect {:?}: {:?}", crit_effect, critical_sniper.average_scores());
+  }
+  let reroll_armor = &effects::RerollArmor{};
+  let extra_armor_die = &effects::ExtraArmorDie{};
+  for &(name, defence) in [("armour reroll", reroll_armor as &dyn attack::Effect), ("extra armour die", extra_armor_die)].iter() {
+    let defended_sniper = attack::Disposition {
+      effects: vec![shred_on_bottle, damage_on_star, defence],
+      ..sniper.clone()
+    };
+    println!("  against {}: {:?}", name, defended_sniper.average_scores());
+  }
+
+  let big_guy = attack::Disposition {
+    dice: vec![
+      dice::Red,
+      dice::White,
+      dice::Black,
+      dice::Black,
+      dice::Yellow,
+      dice::Green,
+      dice::Green
+    ],
+    characteristics: attack::Characteristics {
+      base_score: dice::new_scores(&[
+        (dice::Damage, 1),
+      ]),
+      required_skill: 9,
+      target: attack::Target {
+        physical_armor: attack::Armor { soft_armor: 2, hard_armor: 1 },
+        ..Default::default()
+      },
+      ..Default::default()
+    },
+    effects: vec![rbb, rab, hit_luck, miss_luck, ignore_armor_on_bottles, armor_luck, crit_luck],
+    ..Default::default()
+  };
+
+  println!("big guy: {:?}", big_guy.average_scores());
+  println!("big guy effects: {:?}", big_guy.explain());
+  for marginal in analysis::effect_ablation(&big_guy) {
+    println!("  without effect {:?}: {:?}", marginal.effects, marginal.delta);
+  }
+  for marginal in analysis::die_additions(&sniper) {
+    println!("  sniper with extra {:?}: {:?}", marginal.die, marginal.delta);
+  }
+  for marginal in analysis::die_removals(&sniper) {
+    println!("  sniper without {:?}: {:?}", marginal.die, marginal.delta);
+  }
+
+  let lockpicking = skill::SkillTest {
+    dice: vec![dice::White, dice::Green],
+    required_skill: 5,
+    crit_passes: true,
+    actions_on_pass: true,
+  };
+  println!("lockpicking: {:?}", lockpicking.results());
+
   println!(
     "result: {:?}",
     attack::Disposition {
@@ -133,8 +405,11 @@ fn main() {
           (dice::Damage, 1),
         ]),
         required_skill: 7,
-        soft_armor: 2,
-        hard_armor: 1,
+        target: attack::Target {
+          physical_armor: attack::Armor { soft_armor: 2, hard_armor: 1 },
+          ..Default::default()
+        },
+        ..Default::default()
       },
       ..Default::default()
     }
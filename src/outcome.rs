@@ -12,6 +12,16 @@ pub enum Facet {
 
 pub use self::Facet::*;
 
+impl Facet {
+    #[allow(unused)]
+    pub fn from_name(name: &str) -> Option<Facet> {
+        match name {
+            $(stringify!($variant) => Some($variant),)+
+            _ => None,
+        }
+    }
+}
+
 pub type Scores = enum_map::EnumMap<self::Facet, $Score>;
 
 #[allow(unused)]
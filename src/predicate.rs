@@ -0,0 +1,415 @@
+//! Small language to describe conditions over attack cases, so card rules could be
+//! configured without recompiling. Examples of conditions:
+//!
+//! * `score(Bottle) >= 2`
+//! * `result(Hits) > 0 and not score(Crit) > 0`
+//! * `miss and skill within 2 of required`
+//!
+//! `score(Facet)` refers to dice facets, `result(Facet)` to attack results. `skill` is a shortcut
+//! for `score(Skill)`; `required` refers to characteristics, `soft_armor` and `hard_armor`
+//! to armour of the target against damage of the weapon.
+//! `hit` and `miss` tell if the attack currently hits. Values could be added and subtracted.
+
+use std::fmt;
+use std::str::FromStr;
+use attack;
+use combinators;
+use common::*;
+use dice;
+
+pub struct Condition {
+  expr: Expr,
+}
+
+impl Condition {
+  pub fn holds(&self, case: &attack::Case) -> bool {
+    self.expr.holds(case)
+  }
+}
+
+impl combinators::Predicate for Condition {
+  fn holds(&self, case: &attack::Case) -> bool {
+    Condition::holds(self, case)
+  }
+}
+
+impl FromStr for Condition {
+  type Err = ParseError;
+
+  fn from_str(text: &str) -> Result<Condition, ParseError> {
+    let mut parser = Parser { tokens: tokenize(text)?, idx: 0, end: text.len() };
+    let expr = parser.expr()?;
+    match parser.tokens.get(parser.idx) {
+      Some(&(position, _)) => Err(ParseError::new(position, "expected end of condition")),
+      None => Ok(Condition { expr }),
+    }
+  }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ParseError {
+  pub position: usize,
+  pub message: String,
+}
+
+impl ParseError {
+  fn new(position: usize, message: &str) -> ParseError {
+    ParseError { position, message: message.to_string() }
+  }
+}
+
+impl fmt::Display for ParseError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{} at position {}", self.message, self.position)
+  }
+}
+
+impl ::std::error::Error for ParseError {}
+
+enum Expr {
+  Hit,
+  Miss,
+  Not(Box<Expr>),
+  And(Box<Expr>, Box<Expr>),
+  Or(Box<Expr>, Box<Expr>),
+  Compare(Value, Comparison, Value),
+  Within(Value, Value, Value),
+}
+
+impl Expr {
+  fn holds(&self, case: &attack::Case) -> bool {
+    match *self {
+      Expr::Hit => case.result(attack::Hits) > 0.0,
+      Expr::Miss => case.result(attack::Hits) == 0.0,
+      Expr::Not(ref expr) => !expr.holds(case),
+      Expr::And(ref lhs, ref rhs) => lhs.holds(case) && rhs.holds(case),
+      Expr::Or(ref lhs, ref rhs) => lhs.holds(case) || rhs.holds(case),
+      Expr::Compare(ref lhs, comparison, ref rhs) => comparison.holds(lhs.value(case), rhs.value(case)),
+      Expr::Within(ref lhs, ref amount, ref rhs) => (lhs.value(case) - rhs.value(case)).abs() <= amount.value(case),
+    }
+  }
+}
+
+enum Value {
+  Number(Real),
+  Score(dice::Facet),
+  Result(attack::Facet),
+  RequiredSkill,
+  SoftArmor,
+  HardArmor,
+  Sum(Box<Value>, Box<Value>),
+  Difference(Box<Value>, Box<Value>),
+}
+
+impl Value {
+  fn value(&self, case: &attack::Case) -> Real {
+    match *self {
+      Value::Number(number) => number,
+      Value::Score(facet) => case.score(facet) as Real,
+      Value::Result(facet) => case.result(facet),
+      Value::RequiredSkill => case.characteristics().required_skill as Real,
+      Value::SoftArmor => case.characteristics().target_armor().soft_armor as Real,
+      Value::HardArmor => case.characteristics().target_armor().hard_armor as Real,
+      Value::Sum(ref lhs, ref rhs) => lhs.value(case) + rhs.value(case),
+      Value::Difference(ref lhs, ref rhs) => lhs.value(case) - rhs.value(case),
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Comparison {
+  Less,
+  LessOrEqual,
+  Equal,
+  NotEqual,
+  GreaterOrEqual,
+  Greater,
+}
+
+impl Comparison {
+  fn holds(self, lhs: Real, rhs: Real) -> bool {
+    match self {
+      Comparison::Less => lhs < rhs,
+      Comparison::LessOrEqual => lhs <= rhs,
+      Comparison::Equal => lhs == rhs,
+      Comparison::NotEqual => lhs != rhs,
+      Comparison::GreaterOrEqual => lhs >= rhs,
+      Comparison::Greater => lhs > rhs,
+    }
+  }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+  Word(String),
+  Number(Real),
+  Open,
+  Close,
+  Plus,
+  Minus,
+  Compare(Comparison),
+}
+
+fn tokenize(text: &str) -> Result<Vec<(usize, Token)>, ParseError> {
+  let mut tokens = vec![];
+  let mut chars = text.char_indices().peekable();
+  while let Some((position, c)) = chars.next() {
+    let token = match c {
+      _ if c.is_whitespace() => continue,
+      '(' => Token::Open,
+      ')' => Token::Close,
+      '+' => Token::Plus,
+      '-' => Token::Minus,
+      '<' | '>' | '=' | '!' => {
+        let with_equal = chars.peek().map(|&(_, next)| next) == Some('=');
+        if with_equal {
+          chars.next();
+        }
+        Token::Compare(match (c, with_equal) {
+          ('<', false) => Comparison::Less,
+          ('<', true) => Comparison::LessOrEqual,
+          ('=', true) => Comparison::Equal,
+          ('!', true) => Comparison::NotEqual,
+          ('>', true) => Comparison::GreaterOrEqual,
+          ('>', false) => Comparison::Greater,
+          _ => return Err(ParseError::new(position, "unknown operator")),
+        })
+      }
+      _ if c.is_ascii_digit() => {
+        let mut number = c.to_string();
+        while let Some(&(_, next)) = chars.peek() {
+          if !next.is_ascii_digit() && next != '.' {
+            break;
+          }
+          number.push(next);
+          chars.next();
+        }
+        match number.parse() {
+          Ok(number) => Token::Number(number),
+          Err(_) => return Err(ParseError::new(position, "malformed number")),
+        }
+      }
+      _ if c.is_alphabetic() || c == '_' => {
+        let mut word = c.to_string();
+        while let Some(&(_, next)) = chars.peek() {
+          if !next.is_alphanumeric() && next != '_' {
+            break;
+          }
+          word.push(next);
+          chars.next();
+        }
+        Token::Word(word)
+      }
+      _ => return Err(ParseError::new(position, "unexpected character")),
+    };
+    tokens.push((position, token));
+  }
+  Ok(tokens)
+}
+
+struct Parser {
+  tokens: Vec<(usize, Token)>,
+  idx: usize,
+  end: usize,
+}
+
+impl Parser {
+  fn expr(&mut self) -> Result<Expr, ParseError> {
+    let mut expr = self.conjunction()?;
+    while self.skip_word("or") {
+      expr = Expr::Or(Box::new(expr), Box::new(self.conjunction()?));
+    }
+    Ok(expr)
+  }
+
+  fn conjunction(&mut self) -> Result<Expr, ParseError> {
+    let mut expr = self.unary()?;
+    while self.skip_word("and") {
+      expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+    }
+    Ok(expr)
+  }
+
+  fn unary(&mut self) -> Result<Expr, ParseError> {
+    if self.skip_word("not") {
+      return Ok(Expr::Not(Box::new(self.unary()?)));
+    }
+    if self.skip_word("hit") {
+      return Ok(Expr::Hit);
+    }
+    if self.skip_word("miss") {
+      return Ok(Expr::Miss);
+    }
+    if self.peek() == Some(&Token::Open) && !self.parenthesis_holds_value() {
+      self.idx += 1;
+      let expr = self.expr()?;
+      self.expect(Token::Close, "expected ')'")?;
+      return Ok(expr);
+    }
+    let lhs = self.value()?;
+    if self.skip_word("within") {
+      let amount = self.value()?;
+      if !self.skip_word("of") {
+        return Err(ParseError::new(self.position(), "expected 'of'"));
+      }
+      return Ok(Expr::Within(lhs, amount, self.value()?));
+    }
+    match self.next() {
+      Some(Token::Compare(comparison)) => Ok(Expr::Compare(lhs, comparison, self.value()?)),
+      _ => Err(ParseError::new(self.position(), "expected comparison")),
+    }
+  }
+
+  fn value(&mut self) -> Result<Value, ParseError> {
+    let mut value = self.term()?;
+    loop {
+      match self.peek() {
+        Some(&Token::Plus) => {
+          self.idx += 1;
+          value = Value::Sum(Box::new(value), Box::new(self.term()?));
+        }
+        Some(&Token::Minus) => {
+          self.idx += 1;
+          value = Value::Difference(Box::new(value), Box::new(self.term()?));
+        }
+        _ => return Ok(value),
+      }
+    }
+  }
+
+  fn term(&mut self) -> Result<Value, ParseError> {
+    let position = self.position();
+    match self.next() {
+      Some(Token::Number(number)) => Ok(Value::Number(number)),
+      Some(Token::Minus) => Ok(Value::Difference(Box::new(Value::Number(0.0)), Box::new(self.term()?))),
+      Some(Token::Open) => {
+        let value = self.value()?;
+        self.expect(Token::Close, "expected ')'")?;
+        Ok(value)
+      }
+      Some(Token::Word(word)) => match word.as_str() {
+        "skill" => Ok(Value::Score(dice::Skill)),
+        "required" => Ok(Value::RequiredSkill),
+        "soft_armor" => Ok(Value::SoftArmor),
+        "hard_armor" => Ok(Value::HardArmor),
+        "score" => {
+          let name = self.facet_name()?;
+          dice::Facet::from_name(&name.1)
+            .map(Value::Score)
+            .ok_or_else(|| ParseError::new(name.0, "unknown dice facet"))
+        }
+        "result" => {
+          let name = self.facet_name()?;
+          attack::Facet::from_name(&name.1)
+            .map(Value::Result)
+            .ok_or_else(|| ParseError::new(name.0, "unknown result facet"))
+        }
+        _ => Err(ParseError::new(position, "unknown value")),
+      },
+      _ => Err(ParseError::new(position, "expected value")),
+    }
+  }
+
+  fn facet_name(&mut self) -> Result<(usize, String), ParseError> {
+    self.expect(Token::Open, "expected '('")?;
+    let position = self.position();
+    let name = match self.next() {
+      Some(Token::Word(name)) => name,
+      _ => return Err(ParseError::new(position, "expected facet name")),
+    };
+    self.expect(Token::Close, "expected ')'")?;
+    Ok((position, name))
+  }
+
+  /// Tells apart `(skill + 1) > 2` from `(skill > 2)` by looking for a comparison or
+  /// a logical word at the same nesting level before the closing parenthesis.
+  fn parenthesis_holds_value(&self) -> bool {
+    let mut depth = 0;
+    for (_, token) in &self.tokens[self.idx..] {
+      match *token {
+        Token::Open => depth += 1,
+        Token::Close => {
+          depth -= 1;
+          if depth == 0 {
+            return true;
+          }
+        }
+        Token::Compare(_) if depth == 1 => return false,
+        Token::Word(ref word) if depth == 1 => match word.as_str() {
+          "and" | "or" | "not" | "within" | "hit" | "miss" => return false,
+          _ => (),
+        },
+        _ => (),
+      }
+    }
+    false
+  }
+
+  fn skip_word(&mut self, expected: &str) -> bool {
+    let found = match self.peek() {
+      Some(Token::Word(word)) => word == expected,
+      _ => false,
+    };
+    if found {
+      self.idx += 1;
+    }
+    found
+  }
+
+  fn expect(&mut self, expected: Token, message: &str) -> Result<(), ParseError> {
+    let position = self.position();
+    match self.next() {
+      Some(ref token) if *token == expected => Ok(()),
+      _ => Err(ParseError::new(position, message)),
+    }
+  }
+
+  fn peek(&self) -> Option<&Token> {
+    self.tokens.get(self.idx).map(|token| &token.1)
+  }
+
+  fn next(&mut self) -> Option<Token> {
+    let token = self.tokens.get(self.idx).map(|token| token.1.clone());
+    self.idx += 1;
+    token
+  }
+
+  fn position(&self) -> usize {
+    self.tokens.get(self.idx).map_or(self.end, |token| token.0)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use predicate::*;
+  use combinators;
+  use effects;
+
+  #[test]
+  fn parses_comparisons() {
+    assert!("score(Bottle) >= 2".parse::<Condition>().is_ok());
+    assert!("result(Hits) > 0".parse::<Condition>().is_ok());
+    assert!("skill within 2 of required".parse::<Condition>().is_ok());
+    assert!("(skill + 1 > required or hit) and not score(Miss) != 0".parse::<Condition>().is_ok());
+    assert!("(skill - 2) <= required".parse::<Condition>().is_ok());
+  }
+
+  #[test]
+  fn reports_errors() {
+    assert_eq!("score(Bottles) > 1".parse::<Condition>().err().unwrap().position, 6);
+    assert_eq!("skill within 2".parse::<Condition>().err().unwrap().position, 14);
+    assert_eq!("hit hit".parse::<Condition>().err().unwrap().position, 4);
+    assert_eq!("skill # 2".parse::<Condition>().err().unwrap().position, 6);
+  }
+
+  #[test]
+  fn near_miss_as_condition() {
+    let luck = &effects::LuckForHit{};
+    let condition: Condition = "miss and score(Miss) == 0 and skill within 2 of required".parse().unwrap();
+    let configured = &combinators::When(condition, luck);
+    let outcome = attack::Disposition::test_shot(vec![dice::White], vec![configured]).average_scores();
+    let builtin = attack::Disposition::test_shot(vec![dice::White], vec![luck]).average_scores();
+
+    assert_eq!((outcome[attack::Damage] * 1e9).round(), (builtin[attack::Damage] * 1e9).round());
+  }
+}
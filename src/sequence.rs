@@ -0,0 +1,327 @@
+use std::collections::HashMap;
+use std::ptr;
+use common::*;
+use attack;
+use distribution::{ Distribution, whole };
+
+/// Several independent attacks against one target, e.g. a burst or shots of two guns.
+/// Every attack is resolved against armour of the target on its own.
+pub struct AttackSequence<'d, 'a> {
+  /// Attacks are made against the target of the sequence, whatever target they have.
+  pub attacks: Vec<&'d attack::Disposition<'a>>,
+  /// Target is down once it takes damage up to its hit points, and the rest of attacks aren't made.
+  /// Without hit points target goes down only when killed outright, e.g. by a crit.
+  pub target: attack::Target,
+}
+
+#[derive(Debug)]
+pub struct SequenceResults {
+  /// Damage dealt by attacks made before the target is down, up to its hit points.
+  pub damage: Distribution,
+  /// Damage beyond hit points of the target.
+  pub wasted: Distribution,
+  pub hits: Distribution,
+  pub kill_probability: Real,
+}
+
+/// Where a sequence can be after some of its attacks.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct SequenceState {
+  /// Target as it is after the attacks, unless it is down.
+  target: attack::Target,
+  damage: usize,
+  wasted: usize,
+  hits: usize,
+  down: bool,
+}
+
+impl<'d, 'a> AttackSequence<'d, 'a> {
+  pub fn results(&self) -> SequenceResults {
+    // The same disposition is often repeated, e.g. in a burst, so it shares outcomes.
+    let mut outcomes: Vec<Outcomes> = vec![];
+    let mut states = HashMap::new();
+    let start = SequenceState { target: self.target.clone(), damage: 0, wasted: 0, hits: 0, down: false };
+    states.insert(start, 1.0);
+    for &disposition in &self.attacks {
+      let idx = match outcomes.iter().position(|outcomes| ptr::eq(outcomes.disposition, disposition)) {
+        Some(idx) => idx,
+        None => {
+          outcomes.push(Outcomes::new(disposition));
+          outcomes.len() - 1
+        },
+      };
+      let outcomes = &mut outcomes[idx];
+      let mut next = HashMap::new();
+      for (state, probability) in states {
+        if state.down {
+          *next.entry(state).or_insert(0.0) += probability;
+          continue;
+        }
+        for (outcome, &outcome_probability) in outcomes.against(&state.target) {
+          let next_state = SequenceState {
+            target: if outcome.down { state.target.clone() } else { outcome.after(&state.target) },
+            damage: state.damage + outcome.damage,
+            wasted: state.wasted + outcome.wasted,
+            hits: state.hits + outcome.hits,
+            down: outcome.down,
+          };
+          *next.entry(next_state).or_insert(0.0) += probability * outcome_probability;
+        }
+      }
+      states = next;
+    }
+    let mut results = SequenceResults {
+      damage: Distribution::default(),
+      wasted: Distribution::default(),
+      hits: Distribution::default(),
+      kill_probability: 0.0,
+    };
+    for (state, probability) in states {
+      results.damage.add(state.damage, probability);
+      results.wasted.add(state.wasted, probability);
+      results.hits.add(state.hits, probability);
+      if state.down {
+        results.kill_probability += probability;
+      }
+    }
+    results
+  }
+}
+
+/// Distribution of number of attacks which bring the target down, when it is attacked the same way
+/// again and again. Attacks beyond the limit aren't made, so probabilities sum up to the chance
+/// of bringing the target down within the limit. Target without hit points goes down only
+/// when killed outright.
+pub fn attacks_to_kill(disposition: &attack::Disposition, target: &attack::Target, max_attacks: usize) -> Distribution {
+  let mut outcomes = Outcomes::new(disposition);
+  // Probabilities of the target being still up as it is.
+  let mut up = HashMap::new();
+  up.insert(target.clone(), 1.0);
+  let mut attacks = Distribution::default();
+  for attack in 1..=max_attacks {
+    let mut next = HashMap::new();
+    let mut down = 0.0;
+    for (target, probability) in up {
+      for (outcome, &outcome_probability) in outcomes.against(&target) {
+        if outcome.down {
+          down += probability * outcome_probability;
+        } else {
+          *next.entry(outcome.after(&target)).or_insert(0.0) += probability * outcome_probability;
+        }
+      }
+    }
+    attacks.add(attack, down);
+    up = next;
+  }
+  attacks
+}
+
+/// Distribution of number of turns which bring the target down, given attacks made every turn.
+pub fn turns_to_kill(attacks: &Distribution, attacks_per_turn: usize) -> Distribution {
+  let mut turns = Distribution::default();
+  for (count, probability) in attacks.iter() {
+    turns.add(count.div_ceil(attacks_per_turn), probability);
+  }
+  turns
+}
+
+/// What a single attack does to the target, as far as attacks made after it are concerned.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AttackOutcome {
+  /// Damage dealt, up to hit points the target has left.
+  pub damage: usize,
+  /// Damage beyond hit points the target has left.
+  pub wasted: usize,
+  pub hits: usize,
+  /// Radiation points the target takes.
+  pub rad: usize,
+  /// Whether the target is down, either out of hit points or killed outright.
+  pub down: bool,
+}
+
+impl AttackOutcome {
+  /// Target as it is after the attack, which didn't bring it down.
+  pub fn after(&self, target: &attack::Target) -> attack::Target {
+    let hp = target.hp.map(|hp| {
+      PositiveScore::new(hp.get() - self.damage as Score).expect("target should have hit points left while it is up")
+    });
+    // Only radiation points short of the next threshold matter for later attacks.
+    let rad = match target.rad_threshold {
+      Some(threshold) => ((target.rad as usize + self.rad) % threshold.get() as usize) as Score,
+      None => target.rad,
+    };
+    attack::Target { hp, rad, wounded: target.wounded || self.damage > 0, ..target.clone() }
+  }
+}
+
+/// Joint distribution of outcomes of a single attack.
+pub fn attack_outcomes(disposition: &attack::Disposition) -> HashMap<AttackOutcome, Real> {
+  let mut outcomes = HashMap::new();
+  disposition.outcomes(|scores, probability| {
+    let outcome = AttackOutcome {
+      damage: whole(scores[attack::Damage]),
+      wasted: whole(scores[attack::WastedDamage]),
+      hits: whole(scores[attack::Hits]),
+      rad: whole(scores[attack::Radiation]),
+      down: scores[attack::Kills] > 0.0,
+    };
+    *outcomes.entry(outcome).or_insert(0.0) += probability;
+  });
+  outcomes
+}
+
+/// Outcomes of an attack against every state the target comes to. Many ways of attacks lead
+/// to the same state, so outcomes are computed once per state.
+pub struct Outcomes<'d, 'a> {
+  disposition: &'d attack::Disposition<'a>,
+  by_target: HashMap<attack::Target, HashMap<AttackOutcome, Real>>,
+}
+
+impl<'d, 'a> Outcomes<'d, 'a> {
+  pub fn new(disposition: &'d attack::Disposition<'a>) -> Outcomes<'d, 'a> {
+    Outcomes { disposition, by_target: HashMap::new() }
+  }
+
+  pub fn against(&mut self, target: &attack::Target) -> &HashMap<AttackOutcome, Real> {
+    let disposition = self.disposition;
+    self.by_target.entry(target.clone()).or_insert_with(|| attack_outcomes(&disposition.against(target)))
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use sequence::*;
+  use dice;
+
+  /// Every hit deals 2 damage.
+  fn shot() -> attack::Disposition<'static> {
+    let mut shot = attack::Disposition::test_shot(vec![dice::White], vec![]);
+    shot.characteristics.base_score[dice::Damage] = 2;
+    shot.characteristics.required_skill = 10;
+    shot
+  }
+
+  #[test]
+  fn independent_shots_add_up() {
+    let shot = shot();
+    let hit = shot.distribution(attack::Hits).probability(1);
+    let burst = AttackSequence { attacks: vec![&shot, &shot, &shot], target: Default::default() }.results();
+    assert!((burst.hits.probability(3) - hit * hit * hit).abs() < 1e-9);
+    assert!((burst.hits.probability(1) - 3.0 * hit * (1.0 - hit) * (1.0 - hit)).abs() < 1e-9);
+    assert!((burst.damage.mean() - 3.0 * shot.average_scores()[attack::Damage]).abs() < 1e-9);
+    assert_eq!(burst.kill_probability, 0.0);
+  }
+
+  #[test]
+  fn target_down_stops_attacks() {
+    let shot = shot();
+    let hit = shot.distribution(attack::Hits).probability(1);
+    let burst = AttackSequence {
+      attacks: vec![&shot, &shot, &shot],
+      target: attack::Target::test_hp(3),
+    }.results();
+    let miss = 1.0 - hit;
+    assert!((burst.kill_probability - (1.0 - miss * miss * miss - 3.0 * hit * miss * miss)).abs() < 1e-9);
+    assert_eq!(burst.damage.max(), 3);
+    assert!((burst.wasted.probability(1) - burst.kill_probability).abs() < 1e-9);
+    assert!((burst.hits.probability(2) - burst.kill_probability).abs() < 1e-9);
+  }
+
+  #[test]
+  fn attacks_until_target_is_down() {
+    let shot = shot();
+    let hit = shot.distribution(attack::Hits).probability(1);
+    let miss = 1.0 - hit;
+    let attacks = attacks_to_kill(&shot, &attack::Target::test_hp(3), 100);
+    // Second hit brings the target down.
+    assert_eq!(attacks.probability(1), 0.0);
+    assert!((attacks.probability(2) - hit * hit).abs() < 1e-9);
+    assert!((attacks.probability(4) - 3.0 * hit * hit * miss * miss).abs() < 1e-9);
+    assert!((attacks.mean() - 2.0 / hit).abs() < 1e-6);
+  }
+
+  #[test]
+  fn certain_damage_takes_certain_attacks() {
+    let mut stab = attack::Disposition::test_shot(vec![], vec![]);
+    stab.characteristics.base_score[dice::Hit] = 1;
+    for hp in 1..10 {
+      assert_eq!(attacks_to_kill(&stab, &attack::Target::test_hp(hp), 20), Distribution::certain(hp as usize));
+    }
+  }
+
+  #[test]
+  fn kill_brings_target_down_at_once() {
+    let mut execution = attack::Disposition::test_shot(vec![], vec![]);
+    execution.characteristics.base_score = dice::new_scores(&[(dice::Hit, 1), (dice::Crit, 1)]);
+    execution.characteristics.crit_effect = attack::CritEffect::KillWounded;
+    let wounded = attack::Target { wounded: true, ..attack::Target::test_hp(100) };
+    assert_eq!(attacks_to_kill(&execution, &wounded, 3), Distribution::certain(1));
+  }
+
+  /// Every hit gives 2 radiation points and nothing else.
+  fn ray() -> attack::Disposition<'static> {
+    let mut ray = attack::Disposition::test_shot(vec![], vec![]);
+    ray.characteristics.base_score = dice::new_scores(&[(dice::Hit, 1), (dice::Rad, 2)]);
+    ray
+  }
+
+  #[test]
+  fn radiation_builds_up_between_attacks() {
+    let target = attack::Target { rad_threshold: PositiveScore::new(3), ..attack::Target::test_hp(10) };
+    // Every three hits give 6 radiation points, which take 2 HP.
+    assert_eq!(attacks_to_kill(&ray(), &target, 20), Distribution::certain(15));
+    let ray = ray();
+    let burst = AttackSequence { attacks: vec![&ray, &ray], target }.results();
+    assert_eq!(burst.damage, Distribution::certain(1));
+  }
+
+  #[test]
+  fn damaging_hit_wounds_target() {
+    let mut execution = attack::Disposition::test_shot(vec![], vec![]);
+    execution.characteristics.base_score = dice::new_scores(&[(dice::Hit, 1), (dice::Damage, 1), (dice::Crit, 1)]);
+    execution.characteristics.crit_effect = attack::CritEffect::KillWounded;
+    let target = attack::Target::test_hp(100);
+    assert_eq!(attacks_to_kill(&execution, &target, 3), Distribution::certain(2));
+    let burst = AttackSequence { attacks: vec![&execution, &execution], target }.results();
+    assert_eq!(burst.kill_probability, 1.0);
+    assert_eq!(burst.damage, Distribution::certain(2));
+  }
+
+  #[test]
+  fn overkill_of_single_attack_is_wasted() {
+    let shot = shot();
+    let hit = shot.distribution(attack::Hits).probability(1);
+    let single = AttackSequence { attacks: vec![&shot], target: attack::Target::test_hp(1) }.results();
+    assert!((single.damage.probability(1) - hit).abs() < 1e-9);
+    assert!((single.wasted.probability(1) - hit).abs() < 1e-9);
+    assert!((single.kill_probability - hit).abs() < 1e-9);
+  }
+
+  #[test]
+  fn attacks_beyond_limit_are_left_out() {
+    let shot = shot();
+    let attacks = attacks_to_kill(&shot, &attack::Target::test_hp(5), 4);
+    let burst = AttackSequence {
+      attacks: vec![&shot; 4],
+      target: attack::Target::test_hp(5),
+    }.results();
+    assert_eq!(attacks.max(), 4);
+    assert!(attacks.total() < 1.0);
+    // Target still up after all attacks is the mass left out of the distribution.
+    assert!((1.0 - attacks.total() - (1.0 - burst.kill_probability)).abs() < 1e-9);
+  }
+
+  #[test]
+  fn turns_round_attacks_up() {
+    let mut attacks = Distribution::default();
+    for count in 1..=5 {
+      attacks.add(count, 0.2);
+    }
+    let turns = turns_to_kill(&attacks, 2);
+    assert!((turns.probability(1) - 0.4).abs() < 1e-9);
+    assert!((turns.probability(2) - 0.4).abs() < 1e-9);
+    assert!((turns.probability(3) - 0.2).abs() < 1e-9);
+    assert_eq!(turns.max(), 3);
+    assert_eq!(turns_to_kill(&attacks, 1), attacks);
+  }
+}
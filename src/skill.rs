@@ -0,0 +1,70 @@
+use common::*;
+use cartesian_fold::IterExt;
+use distribution::Distribution;
+use attack;
+use dice;
+
+/// A test of skill outside of attacks, such as searching or lockpicking.
+/// No damage or armour is involved, only skill, misses, crits and actions.
+#[derive(Clone, Default)]
+pub struct SkillTest {
+  pub dice: Vec<dice::Type>,
+  pub required_skill: Score,
+  /// Crit passes the test regardless of skill and misses.
+  pub crit_passes: bool,
+  /// Actions are only scored when the test passes, rather than on any roll.
+  pub actions_on_pass: bool,
+}
+
+#[derive(Debug)]
+pub struct SkillTestResults {
+  pub pass_probability: Real,
+  pub actions: Distribution,
+}
+
+impl SkillTest {
+  pub fn results(&self) -> SkillTestResults {
+    let mut results = SkillTestResults { pass_probability: 0.0, actions: Distribution::default() };
+    self.dice
+      .iter()
+      .map(|&die| { dice::RollFace::faces_of(die).iter().cloned() })
+      .cartesian_collections(|faces| {
+        let outcome = attack::roll_outcome(faces);
+        let scores = &outcome.scores;
+        let pass = dice::passes(scores, self.required_skill) || (self.crit_passes && scores[dice::Crit] > 0);
+        if pass {
+          results.pass_probability += outcome.probability;
+        }
+        let actions = if pass || !self.actions_on_pass { scores[dice::Action] } else { 0 };
+        results.actions.add(actions.max(0) as usize, outcome.probability);
+      })
+      .for_each(drop);
+    results
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use skill::*;
+
+  #[test]
+  fn white_die_test() {
+    let test = |required_skill, crit_passes, actions_on_pass| SkillTest {
+      dice: vec![dice::White],
+      required_skill,
+      crit_passes,
+      actions_on_pass,
+    }.results();
+    // Crit faces count as skill 0, action faces as 1, skill faces go from 2 to 10.
+    let easy = test(10, false, false);
+    assert!((easy.pass_probability - 17.0 / 20.0).abs() < 1e-9);
+    assert!((easy.actions.probability(1) - 3.0 / 20.0).abs() < 1e-9);
+    assert_eq!(test(-1, false, false).pass_probability, 0.0);
+    let hard = test(-1, true, true);
+    assert!((hard.pass_probability - 3.0 / 20.0).abs() < 1e-9);
+    assert_eq!(hard.actions.probability(1), 0.0);
+    let average = test(4, false, true);
+    assert!((average.pass_probability - 8.0 / 20.0).abs() < 1e-9);
+    assert!((average.actions.probability(1) - 2.0 / 20.0).abs() < 1e-9);
+  }
+}